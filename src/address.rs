@@ -0,0 +1,327 @@
+use crate::header::decode_words;
+use std::fmt;
+
+// One mailbox from an address field. The local part is kept unquoted and the name has
+// its encoded words decoded.
+pub struct Mailbox {
+    pub name: String,
+    pub local: String,
+    pub domain: String,
+}
+
+impl Mailbox {
+    // The bare address, with the local part quoted again if it needs to be
+    pub fn address(&self) -> String {
+        let local = if !self.local.is_empty() && self.local.split('.').all(is_atom) {
+            self.local.clone()
+        } else {
+            quote(&self.local)
+        };
+        if self.domain.is_empty() {
+            local
+        } else {
+            format!("{}@{}", local, self.domain)
+        }
+    }
+}
+
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, "{}", self.address());
+        }
+        let name = if self.name.split(' ').all(is_atom) {
+            self.name.clone()
+        } else {
+            quote(&self.name)
+        };
+        write!(f, "{} <{}>", name, self.address())
+    }
+}
+
+// Parses an address list such as From, To, Cc or Reply-To (RFC 5322 section 3.4).
+// Groups contribute their members, so "undisclosed-recipients:;" gives none, and the
+// obsolete route in <@relay:user@example.com> is dropped. A comment is taken as the
+// name of an address that has none, as in "user@example.com (User)".
+pub fn parse_addresses(value: &str) -> Vec<Mailbox> {
+    let tokens = tokenize(value);
+    let mut mailboxes = Vec::new();
+    let mut position = 0;
+
+    while position < tokens.len() {
+        let start = position;
+        while position < tokens.len()
+            && !matches!(tokens[position].kind, Kind::Special('<' | ':' | ',' | ';'))
+        {
+            position += 1;
+        }
+        let before = &tokens[start..position];
+
+        match tokens.get(position).map(|token| &token.kind) {
+            // The group's name, whose members follow up to the semicolon
+            Some(Kind::Special(':')) => {
+                position += 1;
+                continue;
+            }
+            Some(Kind::Special('<')) => {
+                let inside = position + 1;
+                position = inside;
+                while position < tokens.len() && tokens[position].kind != Kind::Special('>') {
+                    position += 1;
+                }
+                let mut spec = &tokens[inside..position];
+                if spec.first().map(|token| &token.kind) == Some(&Kind::Special('@')) {
+                    let route_end = spec
+                        .iter()
+                        .position(|token| token.kind == Kind::Special(':'))
+                        .map_or(spec.len(), |end| end + 1);
+                    spec = &spec[route_end..];
+                }
+                let name = phrase(before);
+                mailboxes.extend(mailbox(spec, name, &tokens[inside..position]));
+                // Skip the closing bracket and anything up to the next separator
+                while position < tokens.len()
+                    && !matches!(tokens[position].kind, Kind::Special(',' | ';'))
+                {
+                    position += 1;
+                }
+            }
+            _ => mailboxes.extend(mailbox(before, String::new(), before)),
+        }
+        position += 1;
+    }
+
+    mailboxes
+}
+
+// Builds a mailbox from an addr-spec, taking a comment as the name if there is none
+fn mailbox(spec: &[Token], name: String, with_comments: &[Token]) -> Option<Mailbox> {
+    let words: Vec<&Token> = spec
+        .iter()
+        .filter(|token| !matches!(token.kind, Kind::Comment))
+        .collect();
+    let at = words
+        .iter()
+        .rposition(|token| token.kind == Kind::Special('@'));
+    let (local, domain) = match at {
+        Some(at) => (&words[..at], &words[at + 1..]),
+        None => (&words[..], &words[..0]),
+    };
+    let local: String = local.iter().map(|token| token.text.as_str()).collect();
+    let domain: String = domain.iter().map(|token| token.text.as_str()).collect();
+    if local.is_empty() && domain.is_empty() {
+        return None;
+    }
+
+    let name = if name.is_empty() {
+        with_comments
+            .iter()
+            .rev()
+            .find(|token| token.kind == Kind::Comment)
+            .map(|token| decode_words(token.text.trim()))
+            .unwrap_or_default()
+    } else {
+        name
+    };
+    Some(Mailbox {
+        name,
+        local,
+        domain: domain.to_lowercase(),
+    })
+}
+
+// Joins the words of a display name with single spaces where the original had any
+fn phrase(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        if matches!(token.kind, Kind::Comment) {
+            continue;
+        }
+        if token.space_before && !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&token.text);
+    }
+    decode_words(&text)
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Word,
+    Comment,
+    Special(char),
+}
+
+struct Token {
+    kind: Kind,
+    // Quoted strings, comments and domain literals without their delimiters, apart
+    // from the brackets of a domain literal
+    text: String,
+    space_before: bool,
+}
+
+fn tokenize(value: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = value.chars().peekable();
+    let mut space_before = false;
+
+    while let Some(c) = chars.next() {
+        let (kind, text) = match c {
+            ' ' | '\t' | '\r' | '\n' => {
+                space_before = true;
+                continue;
+            }
+            '"' => (Kind::Word, delimited(&mut chars, '"')),
+            '(' => {
+                // Comments nest
+                let mut text = String::new();
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => text.extend(chars.next()),
+                        '(' => {
+                            depth += 1;
+                            text.push(c);
+                        }
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                            text.push(c);
+                        }
+                        c => text.push(c),
+                    }
+                }
+                (Kind::Comment, text)
+            }
+            '[' => (
+                Kind::Word,
+                format!("[{}]", delimited(&mut chars, ']').trim()),
+            ),
+            '<' | '>' | ':' | ';' | '@' | ',' => (Kind::Special(c), c.to_string()),
+            // Part of a dot-atom, or of an obsolete phrase
+            '.' => (Kind::Word, ".".to_string()),
+            c => {
+                let mut text = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "\"()[]<>:;@,.".contains(next) {
+                        break;
+                    }
+                    text.push(next);
+                    chars.next();
+                }
+                (Kind::Word, text)
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text,
+            space_before,
+        });
+        space_before = false;
+    }
+    tokens
+}
+
+// Reads up to the closing delimiter, unescaping quoted pairs. An unterminated string
+// runs to the end of the value.
+fn delimited(chars: &mut std::iter::Peekable<std::str::Chars>, end: char) -> String {
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c if c == end => break,
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn is_atom(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatted(value: &str) -> Vec<String> {
+        parse_addresses(value)
+            .iter()
+            .map(|mailbox| mailbox.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parses_names_and_lowercases_domains() {
+        assert_eq!(
+            formatted("Alice <alice@Example.COM>, bob@example.org"),
+            ["Alice <alice@example.com>", "bob@example.org"]
+        );
+        assert_eq!(
+            formatted("\"Doe, John\" <john@example.com>"),
+            ["\"Doe, John\" <john@example.com>"]
+        );
+        assert_eq!(
+            formatted("John Q. Public <jqp@example.com>"),
+            ["\"John Q. Public\" <jqp@example.com>"]
+        );
+    }
+
+    #[test]
+    fn takes_a_comment_as_the_missing_name() {
+        assert_eq!(
+            formatted("user@example.com (The (nested) User)"),
+            ["\"The (nested) User\" <user@example.com>"]
+        );
+        assert_eq!(
+            formatted("Named (comment) <user@example.com>"),
+            ["Named <user@example.com>"]
+        );
+    }
+
+    #[test]
+    fn expands_groups_and_drops_routes() {
+        assert!(formatted("undisclosed-recipients:;").is_empty());
+        assert_eq!(
+            formatted("Team: a@example.com, b@example.com;, c@example.com"),
+            ["a@example.com", "b@example.com", "c@example.com"]
+        );
+        assert_eq!(
+            formatted("<@relay.example.net:user@example.com>"),
+            ["user@example.com"]
+        );
+    }
+
+    #[test]
+    fn quotes_local_parts_and_keeps_domain_literals() {
+        let mailboxes = parse_addresses("\"john doe\"@example.com, u@[ 192.0.2.1 ]");
+        assert_eq!(mailboxes[0].local, "john doe");
+        assert_eq!(mailboxes[0].address(), "\"john doe\"@example.com");
+        assert_eq!(mailboxes[1].address(), "u@[192.0.2.1]");
+    }
+
+    #[test]
+    fn decodes_encoded_names() {
+        assert_eq!(
+            formatted("=?UTF-8?Q?Caf=C3=A9?= <cafe@example.com>"),
+            ["Café <cafe@example.com>"]
+        );
+    }
+
+    #[test]
+    fn skips_empty_entries_and_survives_unterminated_input() {
+        assert!(formatted("").is_empty());
+        assert_eq!(formatted(",, a@example.com ,"), ["a@example.com"]);
+        assert_eq!(formatted("\"unterminated <x@example.com>").len(), 1);
+        assert_eq!(formatted("Name <x@example.com").len(), 1);
+    }
+}
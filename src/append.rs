@@ -0,0 +1,126 @@
+use crate::copy::confirm_create;
+use crate::email_error::EmailError;
+use crate::helper::{crlf_line_endings, response_code};
+use crate::Server;
+use std::fs;
+use std::io::{self, Read};
+
+impl<'a> Server<'a> {
+    pub fn append(
+        &mut self,
+        folder: &str,
+        file: &str,
+        flags: &str,
+        date: &str,
+    ) -> Result<String, EmailError> {
+        let message = read_message(file)?;
+        let upload = Upload {
+            message,
+            flags: flags.to_string(),
+            date: date.to_string(),
+        };
+        // A message read from stdin leaves nothing there to answer the TRYCREATE prompt
+        let response = self.append_uploads(folder, &[upload], !file.is_empty())?;
+
+        match response_code(&response, "APPENDUID") {
+            Some(appenduid) => Ok(format!("APPENDUID {}\n", appenduid)),
+            None => Ok(String::new()),
+        }
+    }
+
+    // Uploads a single message, offering to create the folder on [TRYCREATE]
+    pub fn append_message(
+        &mut self,
+        folder: &str,
+        message: &[u8],
+        flags: &str,
+        date: &str,
+    ) -> Result<String, EmailError> {
+        let upload = Upload {
+            message: message.to_vec(),
+            flags: flags.to_string(),
+            date: date.to_string(),
+        };
+        self.append_messages(folder, &[upload])
+    }
+
+    // Uploads several messages in one APPEND, which needs MULTIAPPEND for more than one
+    pub fn append_messages(
+        &mut self,
+        folder: &str,
+        uploads: &[Upload],
+    ) -> Result<String, EmailError> {
+        self.append_uploads(folder, uploads, true)
+    }
+
+    fn append_uploads(
+        &mut self,
+        folder: &str,
+        uploads: &[Upload],
+        can_ask: bool,
+    ) -> Result<String, EmailError> {
+        let mut segments = Vec::new();
+        let mut messages = Vec::new();
+        for (i, upload) in uploads.iter().enumerate() {
+            let options = append_options(&upload.flags, &upload.date);
+            if i == 0 {
+                segments.push(format!("APPEND {}{}", folder, options));
+            } else {
+                segments.push(options);
+            }
+            messages.push(crlf_line_endings(&upload.message));
+        }
+        let literals: Vec<&[u8]> = messages.iter().map(|message| message.as_slice()).collect();
+
+        let response = self.run_literal_command(&segments, &literals)?;
+        if self.valid_response(&response) {
+            return Ok(response);
+        }
+
+        if response_code(&response, "TRYCREATE").is_some()
+            && confirm_create(folder, self.create_folders, can_ask)?
+        {
+            self.create(folder)?;
+            let response = self.run_literal_command(&segments, &literals)?;
+            if self.valid_response(&response) {
+                return Ok(response);
+            }
+        }
+
+        Err(EmailError::AppendFailure)
+    }
+}
+
+pub struct Upload {
+    pub message: Vec<u8>,
+    pub flags: String,
+    pub date: String,
+}
+
+// Builds the optional flag list and internal date that precede the message literal
+pub fn append_options(flags: &str, date: &str) -> String {
+    let mut options = String::new();
+    if !flags.is_empty() {
+        options.push_str(&format!(" ({})", flags));
+    }
+    if !date.is_empty() {
+        options.push_str(&format!(" \"{}\"", date));
+    }
+    options.push(' ');
+    options
+}
+
+// Reads the message from a file, or from stdin when no file is given
+fn read_message(file: &str) -> Result<Vec<u8>, EmailError> {
+    let mut message = Vec::new();
+    if file.is_empty() {
+        io::stdin().read_to_end(&mut message)?;
+    } else {
+        message = fs::read(file).map_err(|_| EmailError::FileNotFound)?;
+    }
+
+    if message.is_empty() {
+        return Err(EmailError::EmptyMessage);
+    }
+    Ok(message)
+}
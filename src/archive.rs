@@ -0,0 +1,281 @@
+use crate::arguments::parse_folder;
+use crate::date::{format_search_date, DateTime};
+use crate::email_error::EmailError;
+use crate::helper::to_sequence_set;
+use crate::Server;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many messages are fetched per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+const DEFAULT_TEMPLATE: &str = "Archive/{year}";
+
+// Folders that are purged when no folder is marked \Trash or \Junk
+const PURGE_FOLDERS: [&str; 2] = ["Trash", "Junk"];
+
+// What the archive command does in one run. Messages older than days are moved out
+// of the selected folder into the folder named by the template, where {year} and
+// {month} come from the message's date, and anything in Trash or Junk older than
+// purge_days is deleted for good.
+pub struct Policy<'p> {
+    pub days: Option<u64>,
+    pub purge_days: Option<u64>,
+    // The most messages moved and purged in total
+    pub limit: Option<usize>,
+    // Dates come from INTERNALDATE unless this is "header"
+    pub date_source: &'p str,
+    pub template: &'p str,
+    pub dry_run: bool,
+}
+
+pub fn valid_date_source(date_source: &str) -> bool {
+    matches!(date_source, "" | "internal" | "header")
+}
+
+// One folder from a LIST response
+struct ListEntry {
+    attributes: String,
+    name: String,
+}
+
+impl<'a> Server<'a> {
+    pub fn archive(&mut self, folder: &str, policy: &Policy) -> Result<String, EmailError> {
+        let mut output = String::new();
+        let mut remaining = policy.limit.unwrap_or(usize::MAX);
+
+        let mut archived = 0;
+        if let Some(days) = policy.days {
+            archived = self.archive_folder(folder, days, policy, remaining, &mut output)?;
+            remaining -= archived;
+        }
+
+        let mut purged = 0;
+        if let Some(days) = policy.purge_days {
+            for trash in self.purge_folders()? {
+                if remaining == 0 {
+                    break;
+                }
+                let count = self.purge(&trash, days, policy.dry_run, remaining, &mut output)?;
+                remaining -= count;
+                purged += count;
+            }
+        }
+
+        if policy.dry_run {
+            output.push_str(&format!(
+                "Would archive {} messages and purge {} messages\n",
+                archived, purged
+            ));
+        } else {
+            output.push_str(&format!(
+                "Archived {} messages, purged {} messages\n",
+                archived, purged
+            ));
+        }
+        Ok(output)
+    }
+
+    // Moves the selected folder's old messages, grouped so each destination takes a
+    // single MOVE. Returns how many messages were, or would be, moved.
+    fn archive_folder(
+        &mut self,
+        folder: &str,
+        days: u64,
+        policy: &Policy,
+        limit: usize,
+        output: &mut String,
+    ) -> Result<usize, EmailError> {
+        let use_header = policy.date_source == "header";
+        let key = if use_header { "SENTBEFORE" } else { "BEFORE" };
+        let cutoff = format_search_date(today() - days as i64);
+        let mut uids = self.uid_search(&format!("{} {}", key, cutoff))?;
+        uids.truncate(limit);
+
+        let items = if use_header {
+            "UID INTERNALDATE BODY.PEEK[HEADER.FIELDS (DATE)]"
+        } else {
+            "UID INTERNALDATE"
+        };
+        let template = if policy.template.is_empty() {
+            DEFAULT_TEMPLATE
+        } else {
+            policy.template
+        };
+        let delimiter = self.delimiter()?;
+
+        let mut destinations: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for batch in uids.chunks(BATCH_MESSAGES) {
+            for fetch in self.uid_fetch(&to_sequence_set(batch), items)? {
+                // A missing or broken Date header falls back to INTERNALDATE
+                let header_date = fetch
+                    .get("BODY[HEADER.FIELDS (DATE)]")
+                    .map(String::from_utf8_lossy)
+                    .and_then(|header| {
+                        let (_, value) = header.split_once(':')?;
+                        DateTime::from_header(value)
+                    });
+                let Some(date) = header_date
+                    .or_else(|| DateTime::from_internal_date(&fetch.text("INTERNALDATE")))
+                else {
+                    eprintln!("Could not find a date for message {}", fetch.uid());
+                    continue;
+                };
+                destinations
+                    .entry(destination(template, &date, &delimiter))
+                    .or_default()
+                    .push(fetch.uid());
+            }
+        }
+
+        let existing: Vec<String> = self
+            .list_folders("*")?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        let source = folder.trim_matches('"');
+        let mut moved = 0;
+        for (destination, uids) in &destinations {
+            let name = destination.trim_matches('"');
+            if policy.dry_run {
+                output.push_str(&format!(
+                    "Would move {} messages from {} to {}\n",
+                    uids.len(),
+                    source,
+                    name
+                ));
+                moved += uids.len();
+                continue;
+            }
+
+            if !existing.iter().any(|folder| folder == name) {
+                self.create(destination)?;
+                output.push_str(&format!("Created folder {}\n", name));
+            }
+            self.uid_move(&to_sequence_set(uids), destination)?;
+            output.push_str(&format!(
+                "Moved {} messages from {} to {}\n",
+                uids.len(),
+                source,
+                name
+            ));
+            moved += uids.len();
+        }
+        Ok(moved)
+    }
+
+    // Permanently removes messages older than days from the folder, which is left
+    // selected afterwards
+    fn purge(
+        &mut self,
+        folder: &str,
+        days: u64,
+        dry_run: bool,
+        limit: usize,
+        output: &mut String,
+    ) -> Result<usize, EmailError> {
+        let folder = parse_folder(folder);
+        self.select(&folder)?;
+        let cutoff = format_search_date(today() - days as i64);
+        let mut uids = self.uid_search(&format!("BEFORE {}", cutoff))?;
+        uids.truncate(limit);
+        if uids.is_empty() {
+            return Ok(0);
+        }
+
+        let name = folder.trim_matches('"');
+        if dry_run {
+            output.push_str(&format!(
+                "Would purge {} messages from {}\n",
+                uids.len(),
+                name
+            ));
+        } else {
+            let uid_set = to_sequence_set(&uids);
+            self.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+            self.uid_expunge(&uid_set)?;
+            output.push_str(&format!("Purged {} messages from {}\n", uids.len(), name));
+        }
+        Ok(uids.len())
+    }
+
+    // Prefers the folders the server marks \Trash and \Junk (RFC 6154), and otherwise
+    // uses the ones with the usual names that exist
+    fn purge_folders(&mut self) -> Result<Vec<String>, EmailError> {
+        let entries = self.list_folders("*")?;
+        let marked: Vec<String> = entries
+            .iter()
+            .filter(|entry| {
+                entry.attributes.split_whitespace().any(|attribute| {
+                    attribute.eq_ignore_ascii_case("\\Trash")
+                        || attribute.eq_ignore_ascii_case("\\Junk")
+                })
+            })
+            .map(|entry| entry.name.clone())
+            .collect();
+        if !marked.is_empty() {
+            return Ok(marked);
+        }
+        Ok(entries
+            .into_iter()
+            .filter(|entry| {
+                PURGE_FOLDERS
+                    .iter()
+                    .any(|name| entry.name.eq_ignore_ascii_case(name))
+            })
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    // The hierarchy delimiter, which LIST reports for an empty pattern
+    fn delimiter(&mut self) -> Result<String, EmailError> {
+        let response = self.run_command("LIST \"\" \"\"")?;
+        if !self.valid_response(&response) {
+            return Err(EmailError::ListFailure);
+        }
+        Ok(response
+            .lines()
+            .find_map(|line| line.strip_prefix("* LIST "))
+            .and_then(|line| line.split_once(") "))
+            .and_then(|(_, rest)| rest.split(' ').next())
+            .map(|delimiter| delimiter.trim_matches('"').to_string())
+            .filter(|delimiter| delimiter.len() == 1)
+            .unwrap_or_else(|| "/".to_string()))
+    }
+
+    fn list_folders(&mut self, pattern: &str) -> Result<Vec<ListEntry>, EmailError> {
+        let command = format!("LIST \"\" \"{}\"", pattern);
+        let response = self.run_command(&command)?;
+        if !self.valid_response(&response) {
+            return Err(EmailError::ListFailure);
+        }
+        Ok(response
+            .lines()
+            .filter_map(|line| line.strip_prefix("* LIST ("))
+            .filter_map(|line| {
+                let (attributes, rest) = line.split_once(") ")?;
+                let (_, name) = rest.split_once(' ')?;
+                Some(ListEntry {
+                    attributes: attributes.to_string(),
+                    name: name.trim().trim_matches('"').to_string(),
+                })
+            })
+            .collect())
+    }
+}
+
+// Fills {year} and {month} from the date, using the server's hierarchy delimiter
+fn destination(template: &str, date: &DateTime, delimiter: &str) -> String {
+    let name = template
+        .replace("{year}", &format!("{:04}", date.year))
+        .replace("{month}", &format!("{:02}", date.month))
+        .replace('/', delimiter);
+    parse_folder(&name)
+}
+
+fn today() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+        .div_euclid(86400)
+}
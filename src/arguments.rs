@@ -1,14 +1,57 @@
+use crate::archive::valid_date_source;
+use crate::date::{valid_internal_date, DATE_FORMATS};
+use crate::deliver::valid_after;
 use crate::email_error::EmailError;
+use crate::header::valid_name;
+use crate::mime::PREFER_TYPES;
+use crate::watch::DEFAULT_INTERVAL;
 
-    pub struct Args {
-        pub folder: String,
-        pub username: String,
-        pub password: String,
-        pub message_number: u32,
-        pub command: String,
-        pub server_name: String,
-        pub use_tsl: bool,
-    }
+pub struct Args {
+    pub folder: String,
+    pub username: String,
+    pub password: String,
+    pub message_number: u32,
+    pub command: String,
+    pub server_name: String,
+    pub use_tsl: bool,
+    pub destination: String,
+    pub file: String,
+    pub flags: String,
+    pub date: String,
+    pub checkpoint: String,
+    pub search: String,
+    pub format: String,
+    pub output: String,
+    pub template: String,
+    pub cache: String,
+    pub offline: bool,
+    pub interval: u64,
+    pub exec: String,
+    pub config: String,
+    pub log: String,
+    pub mda: String,
+    pub maildir: String,
+    pub after: String,
+    pub rules: String,
+    pub dry_run: bool,
+    pub days: Option<u64>,
+    pub purge_days: Option<u64>,
+    pub limit: Option<usize>,
+    pub date_source: String,
+    pub folders: Vec<String>,
+    pub delete: bool,
+    pub operation: Option<u32>,
+    pub raw: bool,
+    pub normalize: bool,
+    pub date_format: String,
+    pub headers: Vec<String>,
+    pub all_headers: bool,
+    pub prefer: String,
+    pub part: String,
+    pub render: bool,
+    pub width: Option<usize>,
+    pub create: bool,
+}
 
 pub fn parse_args(args: &[String]) -> Args {
     let mut parsed_args = Args {
@@ -19,6 +62,43 @@ pub fn parse_args(args: &[String]) -> Args {
         command: String::new(),
         server_name: String::new(),
         use_tsl: false,
+        destination: String::new(),
+        file: String::new(),
+        flags: String::new(),
+        date: String::new(),
+        checkpoint: String::new(),
+        search: String::new(),
+        format: String::new(),
+        output: String::new(),
+        template: String::new(),
+        cache: String::new(),
+        offline: false,
+        interval: DEFAULT_INTERVAL,
+        exec: String::new(),
+        config: String::new(),
+        log: String::new(),
+        mda: String::new(),
+        maildir: String::new(),
+        after: String::new(),
+        rules: String::new(),
+        dry_run: false,
+        days: None,
+        purge_days: None,
+        limit: None,
+        date_source: String::new(),
+        folders: Vec::new(),
+        delete: false,
+        operation: None,
+        raw: false,
+        normalize: false,
+        date_format: String::new(),
+        headers: Vec::new(),
+        all_headers: false,
+        prefer: String::new(),
+        part: String::new(),
+        render: false,
+        width: None,
+        create: false,
     };
 
     let mut iter = args.iter().peekable();
@@ -62,12 +142,309 @@ pub fn parse_args(args: &[String]) -> Args {
                     std::process::exit(1);
                 }
             }
-            "retrieve" | "parse" | "mime" | "list" => {
+            "-d" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.destination = parse_folder(val);
+                } else {
+                    eprintln!("Error: -d flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--file" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.file = val.to_string();
+                } else {
+                    eprintln!("Error: --file flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--flags" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.flags = val.to_string();
+                } else {
+                    eprintln!("Error: --flags flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--date" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.date = val.to_string();
+                } else {
+                    eprintln!("Error: --date flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--checkpoint" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.checkpoint = val.to_string();
+                } else {
+                    eprintln!("Error: --checkpoint flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--search" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.search = val.to_string();
+                } else {
+                    eprintln!("Error: --search flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--format" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.format = val.to_string();
+                } else {
+                    eprintln!("Error: --format flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--output" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.output = val.to_string();
+                } else {
+                    eprintln!("Error: --output flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--template" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.template = val.to_string();
+                } else {
+                    eprintln!("Error: --template flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--cache" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.cache = val.to_string();
+                } else {
+                    eprintln!("Error: --cache flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--interval" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(seconds) = val.parse::<u64>() {
+                        parsed_args.interval = seconds;
+                    } else {
+                        eprintln!("Error: Interval must be a whole number of seconds.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --interval flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--exec" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.exec = val.to_string();
+                } else {
+                    eprintln!("Error: --exec flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--config" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.config = val.to_string();
+                } else {
+                    eprintln!("Error: --config flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--log" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.log = val.to_string();
+                } else {
+                    eprintln!("Error: --log flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--mda" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.mda = val.to_string();
+                } else {
+                    eprintln!("Error: --mda flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--maildir" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.maildir = val.to_string();
+                } else {
+                    eprintln!("Error: --maildir flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--after" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.after = val.to_string();
+                } else {
+                    eprintln!("Error: --after flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--rules" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.rules = val.to_string();
+                } else {
+                    eprintln!("Error: --rules flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--days" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(number) = val.parse() {
+                        parsed_args.days = Some(number);
+                    } else {
+                        eprintln!("Error: Days must be a whole number.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --days flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--purge-days" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(number) = val.parse() {
+                        parsed_args.purge_days = Some(number);
+                    } else {
+                        eprintln!("Error: Purge days must be a whole number.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --purge-days flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--limit" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(number) = val.parse() {
+                        parsed_args.limit = Some(number);
+                    } else {
+                        eprintln!("Error: Limit must be a whole number.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --limit flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--date-source" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.date_source = val.to_string();
+                } else {
+                    eprintln!("Error: --date-source flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--folders" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.folders = val
+                        .split(',')
+                        .map(|folder| folder.trim())
+                        .filter(|folder| !folder.is_empty())
+                        .map(parse_folder)
+                        .collect();
+                } else {
+                    eprintln!("Error: --folders flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--operation" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(number) = val.parse() {
+                        parsed_args.operation = Some(number);
+                    } else {
+                        eprintln!("Error: Operation must be an unsigned integer.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --operation flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--raw" => {
+                parsed_args.raw = true;
+            }
+            "-H" | "--headers" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.headers = val
+                        .split(',')
+                        .map(|field| field.trim().to_string())
+                        .filter(|field| !field.is_empty())
+                        .collect();
+                } else {
+                    eprintln!("Error: --headers flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--all-headers" => {
+                parsed_args.all_headers = true;
+            }
+            "--prefer" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.prefer = val.to_lowercase();
+                } else {
+                    eprintln!("Error: --prefer flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--part" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.part = val.to_string();
+                } else {
+                    eprintln!("Error: --part flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--render" => {
+                parsed_args.render = true;
+            }
+            "--width" => {
+                if let Some(val) = iter.next() {
+                    if let Ok(number) = val.parse() {
+                        parsed_args.width = Some(number);
+                    } else {
+                        eprintln!("Error: Width must be a whole number.");
+                        std::process::exit(1);
+                    }
+                } else {
+                    eprintln!("Error: --width flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--normalize" => {
+                parsed_args.normalize = true;
+            }
+            "--date-format" => {
+                if let Some(val) = iter.next() {
+                    parsed_args.date_format = val.to_string();
+                } else {
+                    eprintln!("Error: --date-format flag requires a value.");
+                    std::process::exit(1);
+                }
+            }
+            "--delete" => {
+                parsed_args.delete = true;
+            }
+            "--create" => {
+                parsed_args.create = true;
+            }
+            "--dry-run" => {
+                parsed_args.dry_run = true;
+            }
+            "retrieve" | "parse" | "mime" | "list" | "copy" | "move" | "append" | "import"
+            | "search" | "export" | "sync" | "watch" | "daemon" | "deliver" | "filter"
+            | "archive" | "dedupe" | "undo" | "journal" | "structure" | "attachments" => {
                 parsed_args.command = arg.to_string();
             }
             "-t" => {
                 parsed_args.use_tsl = true;
             }
+            "--offline" => {
+                parsed_args.offline = true;
+            }
             _ => {
                 parsed_args.server_name = arg.to_string();
             }
@@ -78,13 +455,19 @@ pub fn parse_args(args: &[String]) -> Args {
 }
 
 pub fn validate_args(args: &Args) -> Result<(), EmailError> {
+    // The daemon reads its accounts from the config file instead
+    if args.command == "daemon" {
+        return Ok(());
+    }
     if validate_string(&args.folder) {
         return Err(EmailError::InvalidArguments);
     }
     if validate_string(&args.username) {
         return Err(EmailError::InvalidArguments);
     }
-    if validate_string(&args.password) {
+    // Offline commands read the local cache and never log in, and neither does
+    // listing the journal
+    if !args.offline && args.command != "journal" && validate_string(&args.password) {
         return Err(EmailError::InvalidArguments);
     }
     if validate_string(&args.command) {
@@ -93,14 +476,84 @@ pub fn validate_args(args: &Args) -> Result<(), EmailError> {
     if validate_string(&args.server_name) {
         return Err(EmailError::InvalidArguments);
     }
+    if matches!(args.command.as_str(), "copy" | "move") && validate_string(&args.destination) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "import" && validate_string(&args.file) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "export" && validate_string(&args.output) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "deliver" && args.mda.is_empty() == args.maildir.is_empty() {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "filter" && validate_string(&args.rules) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "archive" && args.days.is_none() && args.purge_days.is_none() {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.command == "dedupe" && args.delete && !args.destination.is_empty() {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.headers.iter().any(|field| !valid_name(field)) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.folders.iter().any(|folder| validate_string(folder)) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !valid_date_source(&args.date_source) || has_line_break(&args.template) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !args.date.is_empty() && !valid_internal_date(&args.date) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !args.date_format.is_empty() && !DATE_FORMATS.contains(&args.date_format.as_str()) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !args.prefer.is_empty() && !PREFER_TYPES.contains(&args.prefer.as_str()) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !args.part.is_empty() && !args.part.split(',').all(valid_part_number) {
+        return Err(EmailError::InvalidArguments);
+    }
+    // Only attachments can save several parts at once
+    if args.command != "attachments" && args.part.contains(',') {
+        return Err(EmailError::InvalidArguments);
+    }
+    if !valid_after(&args.after) {
+        return Err(EmailError::InvalidArguments);
+    }
+    if args.offline
+        && !matches!(
+            args.command.as_str(),
+            "retrieve" | "parse" | "mime" | "list" | "search" | "structure" | "attachments"
+        )
+    {
+        return Err(EmailError::OfflineUnsupported);
+    }
+    if has_line_break(&args.flags) || has_line_break(&args.date) || has_line_break(&args.search) {
+        return Err(EmailError::InvalidArguments);
+    }
     Ok(())
 }
 
+// Section numbers are dot-separated numbers from 1, such as 1.2
+fn valid_part_number(s: &str) -> bool {
+    s.split('.')
+        .all(|number| number.parse::<u32>().is_ok_and(|number| number > 0))
+}
+
+fn has_line_break(s: &str) -> bool {
+    s.contains('\r') || s.contains('\n')
+}
+
 fn validate_string(s: &str) -> bool {
-    s.is_empty() || s.contains('\r') || s.contains('\n')
+    s.is_empty() || has_line_break(s)
 }
 
-pub fn print_args(args: &Args) -> () {
+pub fn print_args(args: &Args) {
     println!("Parsed helper:");
     println!("Folder: {:?}", args.folder);
     println!("Username: {:?}", args.username);
@@ -109,9 +562,46 @@ pub fn print_args(args: &Args) -> () {
     println!("Command: {:?}", args.command);
     println!("Server Name: {:?}", args.server_name);
     println!("Using TSL: {:?}", args.use_tsl);
+    println!("Destination: {:?}", args.destination);
+    println!("File: {:?}", args.file);
+    println!("Flags: {:?}", args.flags);
+    println!("Date: {:?}", args.date);
+    println!("Checkpoint: {:?}", args.checkpoint);
+    println!("Search: {:?}", args.search);
+    println!("Format: {:?}", args.format);
+    println!("Output: {:?}", args.output);
+    println!("Template: {:?}", args.template);
+    println!("Cache: {:?}", args.cache);
+    println!("Offline: {:?}", args.offline);
+    println!("Interval: {:?}", args.interval);
+    println!("Exec: {:?}", args.exec);
+    println!("Config: {:?}", args.config);
+    println!("Log: {:?}", args.log);
+    println!("MDA: {:?}", args.mda);
+    println!("Maildir: {:?}", args.maildir);
+    println!("After: {:?}", args.after);
+    println!("Rules: {:?}", args.rules);
+    println!("Dry Run: {:?}", args.dry_run);
+    println!("Days: {:?}", args.days);
+    println!("Purge Days: {:?}", args.purge_days);
+    println!("Limit: {:?}", args.limit);
+    println!("Date Source: {:?}", args.date_source);
+    println!("Folders: {:?}", args.folders);
+    println!("Delete: {:?}", args.delete);
+    println!("Operation: {:?}", args.operation);
+    println!("Raw: {:?}", args.raw);
+    println!("Normalize: {:?}", args.normalize);
+    println!("Date Format: {:?}", args.date_format);
+    println!("Headers: {:?}", args.headers);
+    println!("All Headers: {:?}", args.all_headers);
+    println!("Prefer: {:?}", args.prefer);
+    println!("Part: {:?}", args.part);
+    println!("Render: {:?}", args.render);
+    println!("Width: {:?}", args.width);
+    println!("Create: {:?}", args.create);
 }
 
-fn parse_folder(folder: &str) -> String {
+pub fn parse_folder(folder: &str) -> String {
     if folder.contains(' ') {
         format!("\"{}\"", folder)
     } else {
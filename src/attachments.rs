@@ -0,0 +1,183 @@
+use crate::email_error::EmailError;
+use crate::encoding::Decoder;
+use crate::export::{sanitize, shorten};
+use crate::part::{parse_parameters, Part};
+use crate::Server;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+// Bodies are decoded this many bytes at a time, so an attachment is written out as it
+// is decoded rather than held in memory whole
+const CHUNK_BYTES: usize = 64 * 1024;
+
+impl<'a> Server<'a> {
+    pub fn attachments(
+        &mut self,
+        message_num: u32,
+        output: &str,
+        parts: &str,
+    ) -> Result<String, EmailError> {
+        let message = self.fetch_bytes(message_num)?;
+        attachments(&message, output, parts)
+    }
+}
+
+// Lists the attachments of a message a line per part, e.g.
+//
+//   2 report.pdf (application/pdf, 31337 bytes)
+//
+// or, given an output directory, saves them there. Parts are all the attachments or
+// those with the comma-separated numbers given, and sizes are after the transfer
+// encoding is undone.
+pub fn attachments(message: &[u8], output: &str, parts: &str) -> Result<String, EmailError> {
+    let root = Part::parse(message);
+    let found = find_attachments(&root);
+    let selected: Vec<&Attachment> = if parts.is_empty() {
+        found.iter().collect()
+    } else {
+        parts
+            .split(',')
+            .map(|number| {
+                found
+                    .iter()
+                    .find(|attachment| attachment.number == number)
+                    .ok_or(EmailError::PartNotFound)
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut response = String::new();
+    if output.is_empty() {
+        for attachment in selected {
+            let size = decode_body(message, attachment.part, &mut io::sink())
+                .map_err(|_| EmailError::AttachmentFailure)?;
+            response.push_str(&format!(
+                "{} {} ({}, {} bytes)\n",
+                attachment.number,
+                attachment.filename,
+                attachment.part.content_type.mime_type,
+                size
+            ));
+        }
+        return Ok(response);
+    }
+
+    fs::create_dir_all(output).map_err(|_| EmailError::AttachmentFailure)?;
+    for attachment in selected {
+        let (path, size) = save(message, attachment, Path::new(output))
+            .map_err(|_| EmailError::AttachmentFailure)?;
+        response.push_str(&format!(
+            "Saved {} to {} ({} bytes)\n",
+            attachment.number,
+            path.display(),
+            size
+        ));
+    }
+    Ok(response)
+}
+
+struct Attachment<'p> {
+    number: String,
+    filename: String,
+    part: &'p Part,
+}
+
+// Parts are attachments when their disposition says so or when they name a file.
+// Multiparts only hold other parts and are never attachments themselves.
+fn find_attachments(root: &Part) -> Vec<Attachment<'_>> {
+    root.numbered()
+        .into_iter()
+        .filter(|numbered| !numbered.part.content_type.is_multipart())
+        .filter_map(|numbered| {
+            let part = numbered.part;
+            let filename = part.filename();
+            let disposition = part
+                .header
+                .get("Content-Disposition")
+                .map(|value| parse_parameters(value).0.to_lowercase());
+            if filename.is_none() && disposition.as_deref() != Some("attachment") {
+                return None;
+            }
+            Some(Attachment {
+                filename: safe_filename(filename.as_deref(), &numbered.number, part),
+                number: numbered.number,
+                part,
+            })
+        })
+        .collect()
+}
+
+// Only the last path component of a name is kept, so names such as ../../.profile or
+// C:\Windows\win.ini cannot reach outside the directory, and it is then sanitized and
+// shortened as export does with header values in file names. Parts without a usable name
+// are named after their number.
+fn safe_filename(filename: Option<&str>, number: &str, part: &Part) -> String {
+    let name = filename
+        .and_then(|filename| filename.rsplit(['/', '\\']).next())
+        .map(|filename| shorten(&sanitize(filename)))
+        .unwrap_or_default();
+    if !name.is_empty() {
+        return name;
+    }
+    if part.content_type.is_message() {
+        format!("part-{}.eml", number)
+    } else {
+        format!("part-{}", number)
+    }
+}
+
+// Writes the decoded body to a new file, removing it again if writing fails partway
+fn save(message: &[u8], attachment: &Attachment, directory: &Path) -> io::Result<(PathBuf, u64)> {
+    let (file, path) = create_unused(directory, &attachment.filename)?;
+    let mut writer = BufWriter::new(file);
+    let written = decode_body(message, attachment.part, &mut writer)
+        .and_then(|size| writer.flush().map(|_| size));
+    match written {
+        Ok(size) => Ok((path, size)),
+        Err(err) => {
+            let _ = fs::remove_file(&path);
+            Err(err)
+        }
+    }
+}
+
+// Existing files are never replaced. A counter is added to the name instead, and the
+// file is created in the same step as the check so nothing can appear in between.
+fn create_unused(directory: &Path, filename: &str) -> io::Result<(File, PathBuf)> {
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (filename, None),
+    };
+    let mut counter = 0;
+    loop {
+        let name = match (counter, extension) {
+            (0, _) => filename.to_string(),
+            (_, Some(extension)) => format!("{}-{}.{}", stem, counter, extension),
+            (_, None) => format!("{}-{}", stem, counter),
+        };
+        let path = directory.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => counter += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Undoes the transfer encoding a chunk at a time, writing each piece as it is decoded,
+// and returns the decoded size
+fn decode_body(message: &[u8], part: &Part, writer: &mut impl Write) -> io::Result<u64> {
+    let mut decoder = Decoder::new(part.header.get("Content-Transfer-Encoding"));
+    let mut decoded = Vec::with_capacity(CHUNK_BYTES);
+    let mut size = 0;
+    for chunk in message[part.body.clone()].chunks(CHUNK_BYTES) {
+        decoder.update(chunk, &mut decoded);
+        writer.write_all(&decoded)?;
+        size += decoded.len() as u64;
+        decoded.clear();
+    }
+    decoder.finish(&mut decoded);
+    writer.write_all(&decoded)?;
+    Ok(size + decoded.len() as u64)
+}
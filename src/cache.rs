@@ -0,0 +1,256 @@
+use crate::email_error::EmailError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// A local copy of one folder, laid out as
+//   <root>/<account>/<folder>/state     UIDVALIDITY, UIDNEXT and HIGHESTMODSEQ last seen
+//   <root>/<account>/<folder>/index     one line per message: uid, internal date, flags
+//   <root>/<account>/<folder>/delivered one line per message the daemon has delivered
+//   <root>/<account>/<folder>/messages/<uid>.eml
+pub struct FolderCache {
+    directory: PathBuf,
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub highest_modseq: u64,
+    pub messages: BTreeMap<u32, CachedMessage>,
+    pub delivered: BTreeSet<u32>,
+}
+
+pub struct CachedMessage {
+    pub internal_date: String,
+    pub flags: Vec<String>,
+}
+
+// Uses $XDG_CACHE_HOME/fetchmail, falling back to ~/.cache/fetchmail
+pub fn default_root() -> PathBuf {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(cache) if !cache.is_empty() => PathBuf::from(cache).join("fetchmail"),
+        _ => PathBuf::from(env::var("HOME").unwrap_or_default())
+            .join(".cache")
+            .join("fetchmail"),
+    }
+}
+
+impl FolderCache {
+    pub fn open(root: &str, account: &str, folder: &str) -> Result<FolderCache, EmailError> {
+        let directory = folder_directory(root, account, folder);
+        fs::create_dir_all(directory.join("messages")).map_err(|_| EmailError::CacheFailure)?;
+        Ok(FolderCache::load_from(directory))
+    }
+
+    // Opens a folder that has been synced before, without creating anything
+    pub fn open_existing(
+        root: &str,
+        account: &str,
+        folder: &str,
+    ) -> Result<FolderCache, EmailError> {
+        let directory = folder_directory(root, account, folder);
+        if !directory.join("state").is_file() {
+            return Err(EmailError::FolderNotCached);
+        }
+        Ok(FolderCache::load_from(directory))
+    }
+
+    fn load_from(directory: PathBuf) -> FolderCache {
+        let mut cache = FolderCache {
+            directory,
+            uid_validity: 0,
+            uid_next: 0,
+            highest_modseq: 0,
+            messages: BTreeMap::new(),
+            delivered: BTreeSet::new(),
+        };
+        cache.load();
+        cache
+    }
+
+    fn load(&mut self) {
+        if let Ok(state) = fs::read_to_string(self.directory.join("state")) {
+            for line in state.lines() {
+                let Some((key, value)) = line.split_once(' ') else {
+                    continue;
+                };
+                match key {
+                    "uidvalidity" => self.uid_validity = value.parse().unwrap_or(0),
+                    "uidnext" => self.uid_next = value.parse().unwrap_or(0),
+                    "highestmodseq" => self.highest_modseq = value.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+
+        if let Ok(index) = fs::read_to_string(self.directory.join("index")) {
+            for line in index.lines() {
+                let mut fields = line.split('\t');
+                let Some(Ok(uid)) = fields.next().map(|uid| uid.parse()) else {
+                    continue;
+                };
+                let internal_date = fields.next().unwrap_or_default().to_string();
+                let flags = fields
+                    .next()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|flag| flag.to_string())
+                    .collect();
+                self.messages.insert(
+                    uid,
+                    CachedMessage {
+                        internal_date,
+                        flags,
+                    },
+                );
+            }
+        }
+
+        // Caches from before delivery was tracked only ever delivered what each sync
+        // downloaded, so everything already in them counts as delivered
+        match fs::read_to_string(self.directory.join("delivered")) {
+            Ok(delivered) => {
+                self.delivered = delivered
+                    .lines()
+                    .filter_map(|line| line.parse().ok())
+                    .collect()
+            }
+            Err(_) => self.delivered = self.messages.keys().copied().collect(),
+        }
+    }
+
+    // Writes the state, index and delivered list, replacing the old files only once fully
+    // written
+    pub fn save(&self) -> Result<(), EmailError> {
+        let state = format!(
+            "uidvalidity {}\nuidnext {}\nhighestmodseq {}\n",
+            self.uid_validity, self.uid_next, self.highest_modseq
+        );
+
+        let mut index = String::new();
+        for (uid, message) in &self.messages {
+            index.push_str(&format!(
+                "{}\t{}\t{}\n",
+                uid,
+                message.internal_date,
+                message.flags.join(" ")
+            ));
+        }
+
+        let delivered: String = self
+            .delivered
+            .iter()
+            .map(|uid| format!("{}\n", uid))
+            .collect();
+
+        self.replace("state", state.as_bytes())?;
+        self.replace("index", index.as_bytes())?;
+        self.replace("delivered", delivered.as_bytes())
+    }
+
+    // Cached messages the daemon has not delivered yet
+    pub fn undelivered(&self) -> Vec<u32> {
+        self.messages
+            .keys()
+            .copied()
+            .filter(|uid| !self.delivered.contains(uid))
+            .collect()
+    }
+
+    fn replace(&self, name: &str, contents: &[u8]) -> Result<(), EmailError> {
+        let temporary = self.directory.join(format!("{}.tmp", name));
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, self.directory.join(name)))
+            .map_err(|_| EmailError::CacheFailure)
+    }
+
+    fn message_path(&self, uid: u32) -> PathBuf {
+        self.directory.join("messages").join(format!("{}.eml", uid))
+    }
+
+    pub fn read_message(&self, uid: u32) -> Result<Vec<u8>, EmailError> {
+        if !self.messages.contains_key(&uid) {
+            return Err(EmailError::MessageNotCached);
+        }
+        fs::read(self.message_path(uid)).map_err(|_| EmailError::MessageNotCached)
+    }
+
+    // Drops every cached message, used when the server's UIDVALIDITY changes
+    pub fn clear(&mut self, uid_validity: u32) -> Result<(), EmailError> {
+        let uids: Vec<u32> = self.messages.keys().copied().collect();
+        for uid in uids {
+            self.remove(uid)?;
+        }
+        self.uid_validity = uid_validity;
+        self.uid_next = 0;
+        self.highest_modseq = 0;
+        Ok(())
+    }
+
+    pub fn store(
+        &mut self,
+        uid: u32,
+        internal_date: &str,
+        flags: Vec<String>,
+        message: &[u8],
+    ) -> Result<(), EmailError> {
+        fs::write(self.message_path(uid), message).map_err(|_| EmailError::CacheFailure)?;
+        self.messages.insert(
+            uid,
+            CachedMessage {
+                internal_date: internal_date.to_string(),
+                flags,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, uid: u32) -> Result<(), EmailError> {
+        self.messages.remove(&uid);
+        self.delivered.remove(&uid);
+        match fs::remove_file(self.message_path(uid)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(_) => Err(EmailError::CacheFailure),
+        }
+    }
+
+    // Returns true when the flags actually changed
+    pub fn set_flags(&mut self, uid: u32, flags: Vec<String>) -> bool {
+        match self.messages.get_mut(&uid) {
+            Some(message) if message.flags != flags => {
+                message.flags = flags;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// The given cache root, or the default one when none was given
+pub fn root_directory(root: &str) -> PathBuf {
+    if root.is_empty() {
+        default_root()
+    } else {
+        PathBuf::from(root)
+    }
+}
+
+fn folder_directory(root: &str, account: &str, folder: &str) -> PathBuf {
+    root_directory(root)
+        .join(path_component(account))
+        .join(path_component(folder.trim_matches('"')))
+}
+
+// Keeps account and folder names to a single safe path component
+pub fn path_component(name: &str) -> String {
+    let component: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .collect();
+    match component.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => component,
+    }
+}
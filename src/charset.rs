@@ -0,0 +1,368 @@
+use std::ffi::CString;
+
+// Text converted to UTF-8, with the charset it was read as and how many bytes could
+// not be decoded. Those are replaced with U+FFFD, except in ASCII, where they are read
+// as Latin-1.
+pub struct Converted {
+    pub text: String,
+    pub charset: String,
+    pub replaced: usize,
+}
+
+// Decodes text in the charset given, returning None for a charset that is not known
+// here or to iconv. Undecodable bytes are replaced.
+pub fn decode(bytes: &[u8], charset: &str) -> Option<String> {
+    convert(bytes, &canonical(charset)).map(|converted| converted.text)
+}
+
+// Converts a body to UTF-8 from its declared charset. When none is declared, or it is
+// not one that can be read, the charset is guessed from the bytes instead.
+pub fn to_utf8(bytes: &[u8], declared: Option<&str>) -> Converted {
+    declared
+        .map(canonical)
+        .and_then(|charset| convert(bytes, &charset))
+        .unwrap_or_else(|| {
+            let charset = detect(bytes);
+            convert(bytes, charset).unwrap_or_else(|| latin1(bytes, charset))
+        })
+}
+
+// Maps the many names charsets go by onto one each. Names in the GB family are read as
+// GB18030, which is a superset of GB2312 and GBK, and Korean as its superset CP949.
+fn canonical(charset: &str) -> String {
+    let name = charset.trim().trim_matches('"').to_ascii_lowercase();
+    let canonical = match name.as_str() {
+        "utf-8" | "utf8" | "unicode-1-1-utf-8" => "utf-8",
+        "us-ascii" | "ascii" | "us" | "ansi_x3.4-1968" | "iso646-us" | "cp367" => "us-ascii",
+        "iso-8859-1" | "iso_8859-1" | "iso8859-1" | "latin1" | "latin-1" | "l1" | "cp819"
+        | "ibm819" => "iso-8859-1",
+        "windows-1252" | "cp1252" | "x-cp1252" => "windows-1252",
+        "iso-8859-15" | "iso_8859-15" | "iso8859-15" | "latin9" | "latin-9" => "iso-8859-15",
+        "windows-1251" | "cp1251" | "x-cp1251" => "windows-1251",
+        "koi8-r" | "koi8r" | "cskoi8r" => "koi8-r",
+        "koi8-u" | "koi8u" => "koi8-u",
+        "gb18030" | "gb2312" | "gbk" | "cp936" | "x-gbk" | "euc-cn" | "csgb2312" => "gb18030",
+        "iso-2022-jp" | "csiso2022jp" => "iso-2022-jp",
+        "shift_jis" | "shift-jis" | "sjis" | "x-sjis" | "ms_kanji" | "windows-31j" | "cp932" => {
+            "cp932"
+        }
+        "euc-jp" | "x-euc-jp" => "euc-jp",
+        "euc-kr" | "ks_c_5601-1987" | "cp949" | "ks_c_5601" => "cp949",
+        "big5" | "x-big5" | "csbig5" => "big5",
+        _ => return name,
+    };
+    canonical.to_string()
+}
+
+// Guesses the charset of undeclared text. Valid UTF-8 is almost never an accident,
+// escape sequences mark ISO-2022-JP, and Cyrillic and Chinese text is told apart by
+// how its 8-bit bytes are spread. Anything else with 8-bit bytes is taken as
+// Windows-1252, which is what most mislabelled Western mail turns out to be.
+fn detect(bytes: &[u8]) -> &'static str {
+    if bytes.is_ascii() {
+        if bytes
+            .windows(3)
+            .any(|window| matches!(window, b"\x1b$B" | b"\x1b$@" | b"\x1b(J"))
+        {
+            "iso-2022-jp"
+        } else {
+            "us-ascii"
+        }
+    } else if std::str::from_utf8(bytes).is_ok() {
+        "utf-8"
+    } else if let Some(charset) = cyrillic(bytes) {
+        charset
+    } else if gb18030(bytes) {
+        "gb18030"
+    } else {
+        "windows-1252"
+    }
+}
+
+// Russian is written in words made only of 8-bit bytes, and its ten most common
+// letters make up about half of them. Which of the two encodings those letters fall in
+// decides between KOI8-R and Windows-1251. Western text has single accented letters
+// among ASCII ones instead.
+fn cyrillic(bytes: &[u8]) -> Option<&'static str> {
+    // о е а и н т с р в л in each encoding
+    const KOI8_R: &[u8] = b"\xcf\xc5\xc1\xc9\xce\xd4\xd3\xd2\xd7\xcc";
+    const WINDOWS_1251: &[u8] = b"\xee\xe5\xe0\xe8\xed\xf2\xf1\xf0\xe2\xeb";
+
+    let high = bytes.iter().filter(|byte| !byte.is_ascii()).count();
+    let adjacent = bytes
+        .windows(2)
+        .filter(|pair| !pair[0].is_ascii() && !pair[1].is_ascii())
+        .count();
+    let common = |letters: &[u8]| bytes.iter().filter(|byte| letters.contains(byte)).count();
+    let (koi8_r, windows_1251) = (common(KOI8_R), common(WINDOWS_1251));
+    if adjacent * 2 < high || koi8_r.max(windows_1251) * 5 < high * 2 {
+        return None;
+    }
+    Some(if koi8_r >= windows_1251 {
+        "koi8-r"
+    } else {
+        "windows-1251"
+    })
+}
+
+// GB18030 text is 8-bit bytes in pairs, or in fours with digits second and fourth, and
+// nearly all of the pairs are the GB2312 characters Chinese mostly uses
+fn gb18030(bytes: &[u8]) -> bool {
+    let (mut characters, mut gb2312) = (0, 0);
+    let mut i = 0;
+    while i < bytes.len() {
+        let lead = bytes[i];
+        if lead.is_ascii() {
+            i += 1;
+            continue;
+        }
+        if !(0x81..=0xfe).contains(&lead) {
+            return false;
+        }
+        match bytes.get(i + 1..(i + 4).min(bytes.len())) {
+            Some([0x30..=0x39, 0x81..=0xfe, 0x30..=0x39]) => i += 4,
+            Some([trail @ (0x40..=0x7e | 0x80..=0xfe), ..]) => {
+                if (0xa1..=0xf7).contains(&lead) && *trail >= 0xa1 {
+                    gb2312 += 1;
+                }
+                i += 2;
+            }
+            _ => return false,
+        }
+        characters += 1;
+    }
+    characters > 0 && gb2312 * 10 >= characters * 9
+}
+
+fn convert(bytes: &[u8], charset: &str) -> Option<Converted> {
+    match charset {
+        "utf-8" => {
+            let text = String::from_utf8_lossy(bytes).to_string();
+            let replaced = match std::str::from_utf8(bytes) {
+                Ok(_) => 0,
+                Err(_) => text.matches('\u{fffd}').count(),
+            };
+            Some(Converted {
+                text,
+                charset: charset.to_string(),
+                replaced,
+            })
+        }
+        // Anything above 0x7f is invalid, but ASCII is often declared for Latin-1 text,
+        // so such bytes are read as Latin-1 and counted as not decoded
+        "us-ascii" => Some(Converted {
+            replaced: bytes.iter().filter(|byte| !byte.is_ascii()).count(),
+            ..latin1(bytes, charset)
+        }),
+        "iso-8859-1" => Some(latin1(bytes, charset)),
+        "windows-1252" => Some(Converted {
+            text: bytes.iter().map(|&byte| cp1252(byte)).collect(),
+            charset: charset.to_string(),
+            replaced: 0,
+        }),
+        _ => iconv(bytes, charset),
+    }
+}
+
+fn latin1(bytes: &[u8], charset: &str) -> Converted {
+    Converted {
+        text: bytes.iter().map(|&byte| byte as char).collect(),
+        charset: charset.to_string(),
+        replaced: 0,
+    }
+}
+
+// Windows-1252 is Latin-1 except for 0x80 to 0x9f, where it has printable characters
+fn cp1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž',
+        '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}',
+        'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
+
+// Converts with the system's iconv, skipping each byte it cannot convert. Names are
+// limited to the characters charset names use, as iconv reads suffixes such as
+// //TRANSLIT as options.
+fn iconv(bytes: &[u8], charset: &str) -> Option<Converted> {
+    if charset.is_empty()
+        || !charset
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+    {
+        return None;
+    }
+    let to = CString::new("UTF-8").ok()?;
+    let from = CString::new(charset).ok()?;
+    let descriptor = unsafe { libc::iconv_open(to.as_ptr(), from.as_ptr()) };
+    if descriptor as isize == -1 {
+        return None;
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(bytes.len() * 2 + 16);
+    let mut replaced = 0;
+    let mut position = 0;
+    let mut buffer = [0u8; 4096];
+    loop {
+        let mut input = bytes[position..].as_ptr() as *mut libc::c_char;
+        let mut input_left = bytes.len() - position;
+        let mut out = buffer.as_mut_ptr() as *mut libc::c_char;
+        let mut out_left = buffer.len();
+        // Input of null flushes any shift state, as ISO-2022-JP has, once all is read
+        let result = if input_left == 0 {
+            unsafe {
+                libc::iconv(
+                    descriptor,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    &mut out,
+                    &mut out_left,
+                )
+            }
+        } else {
+            unsafe {
+                libc::iconv(
+                    descriptor,
+                    &mut input,
+                    &mut input_left,
+                    &mut out,
+                    &mut out_left,
+                )
+            }
+        };
+        let error = std::io::Error::last_os_error().raw_os_error();
+        output.extend_from_slice(&buffer[..buffer.len() - out_left]);
+        let finished = position == bytes.len();
+        position = bytes.len() - input_left;
+
+        if result != usize::MAX {
+            if finished {
+                break;
+            }
+            continue;
+        }
+        match error {
+            Some(libc::E2BIG) => continue,
+            // A sequence that is invalid, or cut off at the end, is replaced a byte at
+            // a time
+            Some(libc::EILSEQ) | Some(libc::EINVAL) if position < bytes.len() => {
+                output.extend_from_slice("\u{fffd}".as_bytes());
+                replaced += 1;
+                position += 1;
+            }
+            _ => break,
+        }
+    }
+    unsafe { libc::iconv_close(descriptor) };
+
+    Some(Converted {
+        text: String::from_utf8_lossy(&output).to_string(),
+        charset: charset.to_string(),
+        replaced,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUSSIAN: &str = "Привет, как дела? Это тестовое сообщение о погоде в Москве.";
+
+    fn encode(text: &str, charset: &str) -> Vec<u8> {
+        // The reverse of iconv above, only for building test input
+        let to = CString::new(charset).unwrap();
+        let from = CString::new("UTF-8").unwrap();
+        let descriptor = unsafe { libc::iconv_open(to.as_ptr(), from.as_ptr()) };
+        assert_ne!(descriptor as isize, -1);
+        let mut input = text.as_ptr() as *mut libc::c_char;
+        let mut input_left = text.len();
+        let mut buffer = vec![0u8; text.len() * 4 + 16];
+        let mut out = buffer.as_mut_ptr() as *mut libc::c_char;
+        let mut out_left = buffer.len();
+        unsafe {
+            libc::iconv(
+                descriptor,
+                &mut input,
+                &mut input_left,
+                &mut out,
+                &mut out_left,
+            );
+            libc::iconv(
+                descriptor,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut out,
+                &mut out_left,
+            );
+            libc::iconv_close(descriptor);
+        }
+        buffer.truncate(buffer.len() - out_left);
+        buffer
+    }
+
+    #[test]
+    fn reads_declared_charsets_by_any_alias() {
+        assert_eq!(decode(b"caf\xe9", "\"Latin1\"").as_deref(), Some("café"));
+        assert_eq!(
+            decode(b"\x80 \x93x\x94", "CP1252").as_deref(),
+            Some("€ “x”")
+        );
+        assert_eq!(
+            decode(&encode(RUSSIAN, "KOI8-R"), "koi8r").as_deref(),
+            Some(RUSSIAN)
+        );
+        assert_eq!(
+            decode(&encode("日本語", "ISO-2022-JP"), "csISO2022JP").as_deref(),
+            Some("日本語")
+        );
+        assert_eq!(
+            decode(&encode("你好", "GB18030"), "gb2312").as_deref(),
+            Some("你好")
+        );
+        assert_eq!(decode(b"abc", "x-no-such-charset"), None);
+        assert_eq!(decode(b"abc", "utf-8//TRANSLIT"), None);
+    }
+
+    #[test]
+    fn counts_what_cannot_be_decoded() {
+        let converted = to_utf8(b"ok \xff\xfe end", Some("utf-8"));
+        assert_eq!(converted.text, "ok \u{fffd}\u{fffd} end");
+        assert_eq!(converted.replaced, 2);
+
+        let converted = to_utf8(b"caf\xe9", Some("us-ascii"));
+        assert_eq!(converted.text, "café");
+        assert_eq!(converted.replaced, 1);
+
+        let converted = to_utf8(b"\xa4\xa2\xff", Some("euc-jp"));
+        assert_eq!(converted.text, "あ\u{fffd}");
+        assert_eq!(converted.replaced, 1);
+    }
+
+    #[test]
+    fn detects_undeclared_charsets() {
+        let detected = |bytes: &[u8]| to_utf8(bytes, None).charset;
+        assert_eq!(detected(b"plain"), "us-ascii");
+        assert_eq!(detected("café".as_bytes()), "utf-8");
+        assert_eq!(detected(&encode("日本語", "ISO-2022-JP")), "iso-2022-jp");
+        assert_eq!(detected(&encode(RUSSIAN, "KOI8-R")), "koi8-r");
+        assert_eq!(detected(&encode(RUSSIAN, "WINDOWS-1251")), "windows-1251");
+        assert_eq!(
+            detected(&encode("你好，这是一封关于天气的测试邮件。", "GB18030")),
+            "gb18030"
+        );
+        let french = "Le café était très agréable, à bientôt. Ça va très bien.";
+        assert_eq!(detected(&encode(french, "WINDOWS-1252")), "windows-1252");
+        assert_eq!(detected(b"\xe9t\xe9"), "windows-1252");
+    }
+
+    #[test]
+    fn falls_back_to_detection_for_unknown_declared_charsets() {
+        let converted = to_utf8(&encode(RUSSIAN, "KOI8-R"), Some("x-unknown"));
+        assert_eq!(converted.charset, "koi8-r");
+        assert_eq!(converted.text, RUSSIAN);
+    }
+}
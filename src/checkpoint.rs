@@ -0,0 +1,50 @@
+use crate::email_error::EmailError;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+// Records which items a long running command has finished, one key per line, so an
+// interrupted run can pick up where it left off
+pub struct Checkpoint {
+    file: File,
+    done: HashSet<String>,
+}
+
+impl Checkpoint {
+    pub fn open(path: &str) -> Result<Checkpoint, EmailError> {
+        let mut done = HashSet::new();
+        if let Ok(existing) = File::open(path) {
+            for line in BufReader::new(existing).lines() {
+                done.insert(line.map_err(|_| EmailError::CheckpointFailure)?);
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_| EmailError::CheckpointFailure)?;
+
+        Ok(Checkpoint { file, done })
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.done.contains(key)
+    }
+
+    // Appends the keys and syncs them to disk before returning
+    pub fn record(&mut self, keys: &[String]) -> Result<(), EmailError> {
+        let mut lines = String::new();
+        for key in keys {
+            lines.push_str(key);
+            lines.push('\n');
+        }
+        self.file
+            .write_all(lines.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|_| EmailError::CheckpointFailure)?;
+
+        self.done.extend(keys.iter().cloned());
+        Ok(())
+    }
+}
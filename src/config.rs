@@ -0,0 +1,131 @@
+use crate::arguments::parse_folder;
+use crate::deliver::valid_after;
+use crate::email_error::EmailError;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// One account from the daemon's configuration file, which has a section per account:
+//
+//   [work]
+//   server = imap.example.com
+//   username = alice
+//   password = secret
+//   tls = yes
+//   folders = INBOX, Lists
+//   interval = 300
+//   mda = procmail -f %F
+//   after = seen
+//
+// With mda or maildir set, but not both, every message a poll downloads is also
+// delivered there.
+pub struct Account {
+    pub name: String,
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub use_tls: bool,
+    pub folders: Vec<String>,
+    // Seconds between polls
+    pub interval: u64,
+    pub mda: String,
+    pub maildir: String,
+    // keep, seen or delete, applied on the server once a message is delivered
+    pub after: String,
+}
+
+impl Account {
+    // The cache is keyed the same way as for the sync command, so both share it
+    pub fn cache_key(&self) -> String {
+        format!("{}@{}", self.username, self.server)
+    }
+}
+
+// Uses $XDG_CONFIG_HOME/fetchmail/accounts, falling back to ~/.config/fetchmail/accounts
+pub fn default_path() -> PathBuf {
+    let root = match env::var("XDG_CONFIG_HOME") {
+        Ok(config) if !config.is_empty() => PathBuf::from(config),
+        _ => PathBuf::from(env::var("HOME").unwrap_or_default()).join(".config"),
+    };
+    root.join("fetchmail").join("accounts")
+}
+
+pub fn read_accounts(path: &str, default_interval: u64) -> Result<Vec<Account>, EmailError> {
+    let path = if path.is_empty() {
+        default_path()
+    } else {
+        PathBuf::from(path)
+    };
+    let contents = fs::read_to_string(path).map_err(|_| EmailError::InvalidConfig)?;
+
+    let mut accounts = Vec::new();
+    for line in contents.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            accounts.push(Account {
+                name: name.trim().to_string(),
+                server: String::new(),
+                username: String::new(),
+                password: String::new(),
+                use_tls: true,
+                folders: vec!["INBOX".to_string()],
+                interval: default_interval,
+                mda: String::new(),
+                maildir: String::new(),
+                after: String::new(),
+            });
+            continue;
+        }
+
+        let (Some(account), Some((key, value))) = (accounts.last_mut(), line.split_once('='))
+        else {
+            return Err(EmailError::InvalidConfig);
+        };
+        let value = value.trim();
+        match key.trim() {
+            "server" => account.server = value.to_string(),
+            "username" => account.username = value.to_string(),
+            "password" => account.password = value.to_string(),
+            "tls" => account.use_tls = matches!(value, "yes" | "true" | "on"),
+            "folders" => {
+                account.folders = value
+                    .split(',')
+                    .map(|folder| folder.trim())
+                    .filter(|folder| !folder.is_empty())
+                    .map(parse_folder)
+                    .collect()
+            }
+            "interval" => {
+                account.interval = value.parse().map_err(|_| EmailError::InvalidConfig)?
+            }
+            "mda" => account.mda = value.to_string(),
+            "maildir" => account.maildir = value.to_string(),
+            "after" if valid_after(value) => account.after = value.to_string(),
+            _ => return Err(EmailError::InvalidConfig),
+        }
+    }
+
+    let incomplete = |account: &Account| {
+        [&account.server, &account.username, &account.password]
+            .iter()
+            .any(|value| value.is_empty() || value.contains(['\r', '\n']))
+    };
+    if accounts.is_empty() || accounts.iter().any(incomplete) {
+        return Err(EmailError::InvalidConfig);
+    }
+    // Messages go to one place, so an account cannot name both
+    if let Some(account) = accounts
+        .iter()
+        .find(|account| !account.mda.is_empty() && !account.maildir.is_empty())
+    {
+        eprintln!("Account {} sets both mda and maildir", account.name);
+        return Err(EmailError::InvalidConfig);
+    }
+    Ok(accounts)
+}
@@ -0,0 +1,177 @@
+use crate::email_error::EmailError;
+use crate::helper::{response_code, SequenceSet};
+use crate::Server;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+impl<'a> Server<'a> {
+    pub fn copy(&mut self, message_num: u32, destination: &str) -> Result<String, EmailError> {
+        let uid = self.fetch_uid(message_num)?;
+        let mapping = self.uid_copy(&uid.to_string(), destination)?;
+        Ok(format_mapping(&mapping))
+    }
+
+    pub fn move_message(
+        &mut self,
+        message_num: u32,
+        destination: &str,
+    ) -> Result<String, EmailError> {
+        let uid = self.fetch_uid(message_num)?;
+        let mapping = self.uid_move(&uid.to_string(), destination)?;
+        Ok(format_mapping(&mapping))
+    }
+
+    // Copies the messages and returns the (old, new) UID pairs from COPYUID, if reported
+    pub fn uid_copy(
+        &mut self,
+        uid_set: &str,
+        destination: &str,
+    ) -> Result<Vec<(u32, u32)>, EmailError> {
+        let command = format!("UID COPY {} {}", uid_set, destination);
+        let response = self.run_with_trycreate(&command, destination)?;
+        let mapping = parse_copyuid(&response, uid_set);
+        self.record_transfer("copy", destination, &response, &mapping)?;
+        Ok(mapping)
+    }
+
+    pub fn uid_move(
+        &mut self,
+        uid_set: &str,
+        destination: &str,
+    ) -> Result<Vec<(u32, u32)>, EmailError> {
+        if self.has_capability("MOVE")? {
+            let command = format!("UID MOVE {} {}", uid_set, destination);
+            let response = self.run_with_trycreate(&command, destination)?;
+            let mapping = parse_copyuid(&response, uid_set);
+            self.record_transfer("move", destination, &response, &mapping)?;
+            return Ok(mapping);
+        }
+
+        // Without MOVE, emulate it as described in RFC 6851 section 3.3. The journal
+        // sees the whole thing as one move rather than its steps.
+        let journal = self.journal.take();
+        let command = format!("UID COPY {} {}", uid_set, destination);
+        let result = self
+            .run_with_trycreate(&command, destination)
+            .and_then(|response| {
+                self.uid_store(uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+                self.uid_expunge(uid_set)?;
+                Ok(response)
+            });
+        self.journal = journal;
+        let response = result?;
+        let mapping = parse_copyuid(&response, uid_set);
+        self.record_transfer("move", destination, &response, &mapping)?;
+        Ok(mapping)
+    }
+
+    pub fn uid_store(&mut self, uid_set: &str, flags: &str) -> Result<String, EmailError> {
+        let previous = self.flags_before_store(uid_set)?;
+        let command = format!("UID STORE {} {}", uid_set, flags);
+        let response = self.run_command(&command)?;
+        if !self.valid_response(&response) {
+            return Err(EmailError::StoreFailure);
+        }
+        if let Some(previous) = previous {
+            self.record_flags(previous)?;
+        }
+        Ok(response)
+    }
+
+    // Expunges only the given UIDs, which needs UIDPLUS. A plain EXPUNGE would also
+    // remove every other message marked \Deleted in the folder, so without UIDPLUS the
+    // messages are left flagged \Deleted instead.
+    pub fn uid_expunge(&mut self, uid_set: &str) -> Result<String, EmailError> {
+        if !self.has_capability("UIDPLUS")? {
+            eprintln!(
+                "Server does not support UIDPLUS, so UIDs {} are left flagged \\Deleted rather than expunged",
+                uid_set
+            );
+            return Ok(String::new());
+        }
+        let saved = self.save_before_expunge(uid_set)?;
+        let command = format!("UID EXPUNGE {}", uid_set);
+        let response = self.run_command(&command)?;
+        if !self.valid_response(&response) {
+            return Err(EmailError::StoreFailure);
+        }
+        if let Some(saved) = saved {
+            self.record_expunge(saved)?;
+        }
+        Ok(response)
+    }
+
+    // Runs a command targeting the destination folder, offering to create it on [TRYCREATE]
+    fn run_with_trycreate(
+        &mut self,
+        command: &str,
+        destination: &str,
+    ) -> Result<String, EmailError> {
+        let response = self.run_command(command)?;
+        if self.valid_response(&response) {
+            return Ok(response);
+        }
+
+        if response_code(&response, "TRYCREATE").is_some()
+            && confirm_create(destination, self.create_folders, true)?
+        {
+            self.create(destination)?;
+            let response = self.run_command(command)?;
+            if self.valid_response(&response) {
+                return Ok(response);
+            }
+        }
+
+        Err(EmailError::CopyFailure)
+    }
+}
+
+// Missing folders are created when --create is given. Otherwise the user is asked, but
+// only when stdin is a terminal that still has input for the answer, so runs from cron,
+// the daemon or an append reading the message from stdin never wait on an answer.
+pub fn confirm_create(folder: &str, create: bool, can_ask: bool) -> Result<bool, EmailError> {
+    if create {
+        return Ok(true);
+    }
+    if !can_ask || !io::stdin().is_terminal() {
+        eprintln!(
+            "Folder {} does not exist. Use --create to create it",
+            folder
+        );
+        return Ok(false);
+    }
+
+    eprint!("Folder {} does not exist. Create it? [y/N] ", folder);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// COPYUID carries the destination UIDVALIDITY, then the source and destination UID sets,
+// which pair up in order. Only the UIDs that were asked for are looked up, so a server
+// cannot make a huge list out of a short response.
+pub fn parse_copyuid(response: &str, requested: &str) -> Vec<(u32, u32)> {
+    let Some(code) = response_code(response, "COPYUID") else {
+        return Vec::new();
+    };
+
+    let parts: Vec<&str> = code.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Vec::new();
+    }
+
+    let source = SequenceSet::parse(parts[1], 0);
+    let destination = SequenceSet::parse(parts[2], 0);
+    SequenceSet::parse(requested, 0)
+        .iter()
+        .filter_map(|uid| Some((uid, destination.nth(source.position(uid)?)?)))
+        .collect()
+}
+
+fn format_mapping(mapping: &[(u32, u32)]) -> String {
+    mapping
+        .iter()
+        .map(|(old, new)| format!("{} -> {}\n", old, new))
+        .collect()
+}
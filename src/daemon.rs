@@ -0,0 +1,228 @@
+use crate::cache::{default_root, FolderCache};
+use crate::config::{read_accounts, Account};
+use crate::date::internal_date;
+use crate::deliver::Delivery;
+use crate::email_error::EmailError;
+use crate::server::{create_stream, Server};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A server that stops answering is given up on after this long, until the next poll
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    if signal == libc::SIGHUP {
+        RELOAD.store(true, Ordering::SeqCst);
+    } else {
+        TERMINATE.store(true, Ordering::SeqCst);
+    }
+}
+
+// Polls every configured account at its own interval, syncing each folder into the
+// cache. SIGHUP re-reads the configuration and SIGTERM or SIGINT stop the daemon once
+// the account being polled is done.
+pub fn run_daemon(
+    config_path: &str,
+    cache_root: &str,
+    log_path: &str,
+    default_interval: u64,
+) -> Result<String, EmailError> {
+    let log = Log::open(log_path)?;
+    let mut accounts = read_accounts(config_path, default_interval)?;
+    let mut schedule = Schedule::load(cache_root);
+
+    unsafe {
+        let handler = handle_signal as *const () as libc::sighandler_t;
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+    log.write(&format!("Started with {} accounts", accounts.len()));
+
+    while !TERMINATE.load(Ordering::SeqCst) {
+        if RELOAD.swap(false, Ordering::SeqCst) {
+            // A broken file keeps the accounts already loaded
+            match read_accounts(config_path, default_interval) {
+                Ok(reloaded) => {
+                    accounts = reloaded;
+                    log.write(&format!("Reloaded {} accounts", accounts.len()));
+                }
+                Err(_) => log.write("Could not reload the configuration, keeping the old one"),
+            }
+        }
+
+        for account in &accounts {
+            if TERMINATE.load(Ordering::SeqCst) {
+                break;
+            }
+            if !schedule.is_due(account) {
+                continue;
+            }
+            if let Err(e) = poll(account, cache_root, &log) {
+                log.write(&format!("{}: {}", account.name, e.details().0));
+            }
+            schedule.polled(account);
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    log.write("Stopped");
+    Ok(String::new())
+}
+
+// A folder that fails is logged and the rest are still polled. The session is logged
+// out and the connection closed whether or not polling succeeded.
+fn poll(account: &Account, cache_root: &str, log: &Log) -> Result<(), EmailError> {
+    let mut stream = create_stream(&account.server, account.use_tls)?;
+    stream.set_timeout(Some(READ_TIMEOUT))?;
+
+    let mut server = Server::new(&account.username, &account.password, false);
+    let result = server.connect(stream).and_then(|_| server.login());
+    if result.is_ok() {
+        server.start_journal(cache_root, &account.cache_key(), "daemon");
+        for folder in &account.folders {
+            if let Err(e) = poll_folder(&mut server, account, folder, cache_root, log) {
+                log.write(&format!(
+                    "{} {}: {}",
+                    account.name,
+                    folder.trim_matches('"'),
+                    e.details().0
+                ));
+            }
+        }
+        server.logout();
+    }
+    server.shutdown();
+    result.map(|_| ())
+}
+
+// Syncs the folder, then delivers every cached message not delivered yet. Which ones
+// were delivered is kept with the cache, so a message whose delivery failed, or was
+// cut short by the daemon stopping, is tried again on the next poll.
+fn poll_folder(
+    server: &mut Server,
+    account: &Account,
+    folder: &str,
+    cache_root: &str,
+    log: &Log,
+) -> Result<(), EmailError> {
+    let mut cache = FolderCache::open(cache_root, &account.cache_key(), folder)?;
+    let report = server.sync_folder(folder, &mut cache)?;
+    log.write(&format!(
+        "{} {}: {} new, {} expunged, {} flag changes",
+        account.name,
+        folder.trim_matches('"'),
+        report.new.len(),
+        report.expunged.len(),
+        report.flag_changes
+    ));
+
+    let Some(delivery) = Delivery::new(&account.mda, &account.maildir) else {
+        return Ok(());
+    };
+    let pending = cache.undelivered();
+    let messages: Vec<(u32, Vec<u8>)> = pending
+        .iter()
+        .filter_map(|&uid| Some((uid, cache.read_message(uid).ok()?)))
+        .collect();
+    let delivered = server.deliver_messages(&messages, &delivery);
+    cache.delivered.extend(&delivered);
+    cache.save()?;
+    log.write(&format!(
+        "{} {}: delivered {} of {} pending messages",
+        account.name,
+        folder.trim_matches('"'),
+        delivered.len(),
+        pending.len()
+    ));
+    server.after_delivery(&delivered, &account.after)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+// Writes timestamped lines to the log file, or to stderr when there is none
+struct Log {
+    file: Option<File>,
+}
+
+impl Log {
+    fn open(path: &str) -> Result<Log, EmailError> {
+        if path.is_empty() {
+            return Ok(Log { file: None });
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_| EmailError::LogFailure)?;
+        Ok(Log { file: Some(file) })
+    }
+
+    fn write(&self, message: &str) {
+        let line = format!("{} {}\n", internal_date(now()), message);
+        match &self.file {
+            Some(file) => {
+                let mut file: &File = file;
+                let _ = file.write_all(line.as_bytes());
+            }
+            None => eprint!("{}", line),
+        }
+    }
+}
+
+// When each account was last polled, kept in the cache root so a restarted daemon
+// carries on where it left off instead of polling everything at once
+struct Schedule {
+    path: PathBuf,
+    last_polled: HashMap<String, i64>,
+}
+
+impl Schedule {
+    fn load(cache_root: &str) -> Schedule {
+        let root = if cache_root.is_empty() {
+            default_root()
+        } else {
+            PathBuf::from(cache_root)
+        };
+        let path = root.join("daemon-state");
+        let last_polled = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(name, time)| Some((name.to_string(), time.parse().ok()?)))
+            .collect();
+        Schedule { path, last_polled }
+    }
+
+    fn is_due(&self, account: &Account) -> bool {
+        self.last_polled
+            .get(&account.name)
+            .is_none_or(|&time| now() >= time + account.interval as i64)
+    }
+
+    fn polled(&mut self, account: &Account) {
+        self.last_polled.insert(account.name.clone(), now());
+
+        let contents: String = self
+            .last_polled
+            .iter()
+            .map(|(name, time)| format!("{}\t{}\n", name, time))
+            .collect();
+        let temporary = self.path.with_extension("tmp");
+        let _ = fs::create_dir_all(self.path.parent().unwrap_or(&self.path));
+        let _ = fs::write(&temporary, contents).and_then(|_| fs::rename(&temporary, &self.path));
+    }
+}
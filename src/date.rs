@@ -0,0 +1,565 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Returns the month number (1 to 12) for a three letter month name
+pub fn month_number(name: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|month| month.eq_ignore_ascii_case(name))
+        .map(|index| index as u32 + 1)
+}
+
+// Parses an IMAP search date such as 1-Feb-1994 into days since the Unix epoch
+pub fn search_date(date: &str) -> Option<i64> {
+    let mut parts = date.trim_matches('"').split('-');
+    let day = parts.next()?.parse().ok()?;
+    let month = month_number(parts.next()?)?;
+    let year = parts.next()?.parse().ok()?;
+    Some(days_from_civil(year, month, day))
+}
+
+// Formats days since the Unix epoch as an IMAP search date, e.g. 1-Feb-1994
+pub fn format_search_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{}-{}-{}", day, MONTHS[month as usize - 1], year)
+}
+
+// Converts days since the Unix epoch into a (year, month, day) civil date
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Formats a Unix timestamp as an IMAP INTERNALDATE in UTC, e.g. 03-Jan-1996 01:05:34 +0000
+pub fn internal_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let seconds = timestamp.rem_euclid(86400);
+    format!(
+        "{:02}-{}-{} {:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Whether a date is an RFC 3501 date-time such as 17-Jul-1996 02:44:25 -0700, where the
+// day is two characters padded with a space or a zero, naming a day that exists
+pub fn valid_internal_date(date: &str) -> bool {
+    let bytes = date.as_bytes();
+    if bytes.len() != 26 || !(bytes[0] == b' ' || bytes[0].is_ascii_digit()) {
+        return false;
+    }
+    let shape = b"#d-Mmm-dddd dd:dd:dd +dddd";
+    let matches_shape = bytes
+        .iter()
+        .zip(shape)
+        .skip(1)
+        .all(|(byte, expected)| match expected {
+            b'd' => byte.is_ascii_digit(),
+            b'M' | b'm' => byte.is_ascii_alphabetic(),
+            b'+' => *byte == b'+' || *byte == b'-',
+            _ => byte == expected,
+        });
+    matches_shape
+        && DateTime::from_internal_date(date).is_some_and(|date| date.offset.abs() < 24 * 60)
+}
+
+// Converts the asctime date on an mbox From_ line, e.g. Sat Jan  3 01:05:34 1996,
+// into an IMAP INTERNALDATE
+pub fn asctime_to_internal_date(asctime: &str) -> Option<String> {
+    let parts: Vec<&str> = asctime.split_whitespace().collect();
+    if parts.len() < 5 {
+        return None;
+    }
+
+    let month = month_number(parts[1])?;
+    let day: u32 = parts[2].parse().ok()?;
+    let time = parts[3];
+    let year: u32 = parts[4].parse().ok()?;
+    if time.len() != 8 || time.split(':').any(|part| part.parse::<u32>().is_err()) {
+        return None;
+    }
+
+    // A zone offset sometimes follows the year
+    let zone = match parts.get(5) {
+        Some(zone) if zone.len() == 5 && (zone.starts_with('+') || zone.starts_with('-')) => zone,
+        _ => "+0000",
+    };
+
+    Some(format!(
+        "{:02}-{}-{} {} {}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time,
+        zone
+    ))
+}
+
+// Converts a civil date into days since the Unix epoch
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// A calendar date and time with the UTC offset it was written in
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    // Offset from UTC in minutes
+    pub offset: i32,
+}
+
+impl DateTime {
+    // Parses an IMAP INTERNALDATE such as 17-Jul-1996 02:44:25 -0700
+    pub fn from_internal_date(date: &str) -> Option<DateTime> {
+        let (day_month_year, rest) = date.trim().split_once(' ')?;
+        let mut date_parts = day_month_year.split('-');
+        let day = date_parts.next()?.trim().parse().ok()?;
+        let month = month_number(date_parts.next()?)?;
+        let year = date_parts.next()?.parse().ok()?;
+
+        let (time, zone) = rest.trim().split_once(' ')?;
+        let mut time_parts = time.split(':').map(|part| part.parse::<u32>());
+        let hour = time_parts.next()?.ok()?;
+        let minute = time_parts.next()?.ok()?;
+        let second = time_parts.next()?.ok()?;
+
+        let date = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            offset: parse_offset(zone)?,
+        };
+        date.valid().then_some(date)
+    }
+
+    // Parses a Date header such as Tue, 1 Jul 2003 10:52:37 +0200, where the day name,
+    // the seconds and the zone may all be missing. Comments are ignored, and so is the
+    // obsolete syntax of RFC 5322 section 4.3: two and three digit years, and zone
+    // names such as EST or GMT.
+    pub fn from_header(value: &str) -> Option<DateTime> {
+        let value = strip_comments(value);
+        let value = value
+            .split_once(',')
+            .map_or(value.as_str(), |(_, rest)| rest);
+        let mut words = value.split_whitespace().peekable();
+        // Some senders leave out the comma after the day name
+        if words
+            .peek()
+            .is_some_and(|word| word.chars().all(|c| c.is_ascii_alphabetic()))
+        {
+            words.next();
+        }
+
+        let day = words.next()?.parse().ok()?;
+        let month = month_number(words.next()?)?;
+        let year: i64 = words.next()?.parse().ok()?;
+        let year = match year {
+            0..=49 => year + 2000,
+            50..=999 => year + 1900,
+            _ => year,
+        };
+
+        let mut time_parts = words.next()?.split(':').map(|part| part.parse::<u32>());
+        let hour = time_parts.next()?.ok()?;
+        let minute = time_parts.next()?.ok()?;
+        let second = match time_parts.next() {
+            Some(second) => second.ok()?,
+            None => 0,
+        };
+
+        let date = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            offset: words.next().and_then(zone_offset).unwrap_or(0),
+        };
+        date.valid().then_some(date)
+    }
+
+    // The date and time a timestamp falls on at the offset
+    pub fn from_timestamp(timestamp: i64, offset: i32) -> DateTime {
+        let local = timestamp + offset as i64 * 60;
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        let seconds = local.rem_euclid(86400) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+            offset,
+        }
+    }
+
+    // Years are limited to four digits, which also keeps the day arithmetic from
+    // overflowing on dates such as 1 Jan 99999999999999999
+    fn valid(&self) -> bool {
+        (1000..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            // Rules out days past the end of the month, such as 30 Feb
+            && civil_from_days(self.day_number()) == (self.year, self.month, self.day)
+            && self.hour < 24
+            && self.minute < 60
+            // Leap seconds are allowed
+            && self.second <= 60
+    }
+
+    // Days since the Unix epoch of the date as written
+    pub fn day_number(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    // Seconds since the Unix epoch
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+            - self.offset as i64 * 60
+    }
+
+    // The date as written, e.g. 1996-07-17
+    pub fn iso_date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    // The form used in Date headers, e.g. Wed, 17 Jul 1996 02:44:25 -0700
+    pub fn rfc5322(&self) -> String {
+        format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[self.day_number().rem_euclid(7) as usize],
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+            format_offset(self.offset, "")
+        )
+    }
+
+    // ISO 8601, e.g. 1996-07-17T02:44:25-07:00
+    pub fn iso8601(&self) -> String {
+        format!(
+            "{}T{:02}:{:02}:{:02}{}",
+            self.iso_date(),
+            self.hour,
+            self.minute,
+            self.second,
+            format_offset(self.offset, ":")
+        )
+    }
+
+    // The asctime form used on mbox From_ lines, e.g. Wed Jul 17 02:44:25 1996
+    pub fn asctime(&self) -> String {
+        let days = days_from_civil(self.year, self.month, self.day);
+        format!(
+            "{} {} {:2} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            MONTHS[self.month as usize - 1],
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.year
+        )
+    }
+}
+
+// Day names indexed from the Unix epoch, which fell on a Thursday
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+// Parses a numeric zone such as +1100 or -0730 into minutes
+pub fn parse_offset(zone: &str) -> Option<i32> {
+    let sign = match zone.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = &zone[1..];
+    if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+// Zones RFC 5322 section 4.3 still allows to be read. Military zones other than Z were
+// defined with the wrong sign, so like UT they give no information beyond -0000.
+fn zone_offset(zone: &str) -> Option<i32> {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "UT" | "UTC" | "GMT" | "Z" => 0,
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        zone if zone.len() == 1 && zone.chars().all(|c| c.is_ascii_alphabetic()) => 0,
+        _ => return parse_offset(zone),
+    };
+    Some(hours * 60)
+}
+
+fn format_offset(offset: i32, separator: &str) -> String {
+    format!(
+        "{}{:02}{}{:02}",
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        separator,
+        offset.abs() % 60
+    )
+}
+
+// Removes parenthesized comments, which may nest, such as the (PST) some senders add
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0;
+    let mut text = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => {
+                depth -= 1;
+                text.push(' ');
+            }
+            c if depth == 0 => text.push(c),
+            _ => (),
+        }
+    }
+    text
+}
+
+// The offset from UTC of the local timezone at the moment given
+pub fn local_offset(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    (tm.tm_gmtoff / 60) as i32
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+// How long before or after now a timestamp is, in its largest whole unit
+pub fn relative(timestamp: i64, now: i64) -> String {
+    let seconds = now - timestamp;
+    if seconds.abs() < 60 {
+        return "just now".to_string();
+    }
+    let (count, unit) = [
+        (365 * 86400, "year"),
+        (30 * 86400, "month"),
+        (7 * 86400, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ]
+    .into_iter()
+    .map(|(length, unit)| (seconds.abs() / length, unit))
+    .find(|&(count, _)| count > 0)
+    .unwrap_or((0, "minute"));
+    let plural = if count == 1 { "" } else { "s" };
+    if seconds > 0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
+
+pub const DATE_FORMATS: [&str; 4] = ["original", "iso", "local", "relative"];
+
+// Rewrites a Date header value in one of DATE_FORMATS. A missing or unparseable date is
+// taken from the INTERNALDATE instead, and if neither can be read the value is kept.
+pub fn format_date(
+    value: Option<&str>,
+    internal_date: Option<&str>,
+    format: &str,
+) -> Option<String> {
+    let original = format.is_empty() || format == "original";
+    if original && value.and_then(DateTime::from_header).is_some() {
+        return value.map(|value| value.to_string());
+    }
+    let Some(date) = value
+        .and_then(DateTime::from_header)
+        .or_else(|| internal_date.and_then(DateTime::from_internal_date))
+    else {
+        return value.map(|value| value.to_string());
+    };
+
+    let timestamp = date.timestamp();
+    let text = match format {
+        // Only reached without a usable Date header, so the INTERNALDATE is shown as one
+        _ if original => date.rfc5322(),
+        "iso" => date.iso8601(),
+        "local" => DateTime::from_timestamp(timestamp, local_offset(timestamp)).rfc5322(),
+        _ => relative(timestamp, now()),
+    };
+    Some(format!(" {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iso(header: &str) -> Option<String> {
+        DateTime::from_header(header).map(|date| date.iso8601())
+    }
+
+    #[test]
+    fn reads_date_headers() {
+        assert_eq!(
+            iso("Tue, 1 Jul 2003 10:52:37 +0200"),
+            Some("2003-07-01T10:52:37+02:00".to_string())
+        );
+        assert_eq!(
+            iso("1 Jul 2003 10:52 -0730"),
+            Some("2003-07-01T10:52:00-07:30".to_string())
+        );
+        assert_eq!(
+            iso("Tue 1 Jul 2003 10:52:37 +0000"),
+            Some("2003-07-01T10:52:37+00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_obsolete_years_zones_and_comments() {
+        assert_eq!(
+            iso("Thu, 13 Feb 69 23:32:54 EST"),
+            Some("1969-02-13T23:32:54-05:00".to_string())
+        );
+        assert_eq!(
+            iso("13 Feb 03 23:32 GMT"),
+            Some("2003-02-13T23:32:00+00:00".to_string())
+        );
+        assert_eq!(
+            iso("Fri, 21 Nov 1997 09:55:06 -0800 (PST)"),
+            Some("1997-11-21T09:55:06-08:00".to_string())
+        );
+        assert_eq!(
+            iso("Fri, 21 Nov 1997 09:55:06"),
+            Some("1997-11-21T09:55:06+00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_impossible_dates() {
+        for header in [
+            "30 Feb 2024 10:00:00 +0000",
+            "31 Apr 2024 10:00:00 +0000",
+            "1 Jan 2024 24:00:00 +0000",
+            "1 Foo 2024 10:00:00 +0000",
+            "0 Jan 2024 10:00:00 +0000",
+            "1 Jan 99999999999999999 10:00:00 +0000",
+            "1 Jan -5000 10:00:00 +0000",
+            "sometime last week",
+            "",
+        ] {
+            assert!(DateTime::from_header(header).is_none(), "{}", header);
+        }
+        assert!(DateTime::from_header("29 Feb 2024 10:00:00 +0000").is_some());
+    }
+
+    #[test]
+    fn reads_and_checks_internal_dates() {
+        let date = DateTime::from_internal_date("17-Jul-1996 02:44:25 -0700").unwrap();
+        assert_eq!(date.timestamp(), 837_596_665);
+        assert_eq!(date.rfc5322(), "Wed, 17 Jul 1996 02:44:25 -0700");
+        assert_eq!(internal_date(837_596_665), "17-Jul-1996 09:44:25 +0000");
+
+        assert!(valid_internal_date("17-Jul-1996 02:44:25 -0700"));
+        assert!(valid_internal_date(" 7-Jul-1996 02:44:25 +0000"));
+        assert!(!valid_internal_date("7-Jul-1996 02:44:25 +0000"));
+        assert!(!valid_internal_date("30-Feb-1996 02:44:25 +0000"));
+        assert!(!valid_internal_date("17-Jul-1996 02:44:25 +2400"));
+        assert!(!valid_internal_date("17-Jul-1996 02:44:25 -0700\" x"));
+    }
+
+    #[test]
+    fn converts_asctime_dates() {
+        assert_eq!(
+            asctime_to_internal_date("Sat Jan  3 01:05:34 1996"),
+            Some("03-Jan-1996 01:05:34 +0000".to_string())
+        );
+        assert_eq!(
+            asctime_to_internal_date("Sat Jan  3 01:05:34 1996 -0500"),
+            Some("03-Jan-1996 01:05:34 -0500".to_string())
+        );
+        assert_eq!(asctime_to_internal_date("Sat Jan 3 1996"), None);
+    }
+
+    #[test]
+    fn describes_relative_times() {
+        assert_eq!(relative(1000, 1030), "just now");
+        assert_eq!(relative(0, 2 * 3600), "2 hours ago");
+        assert_eq!(relative(0, 86400), "1 day ago");
+        assert_eq!(relative(3 * 7 * 86400, 0), "in 3 weeks");
+    }
+
+    #[test]
+    fn falls_back_to_the_internal_date_in_every_format() {
+        let internal = Some("17-Jul-1996 02:44:25 -0700");
+        assert_eq!(
+            format_date(Some(" garbage"), internal, "original"),
+            Some(" Wed, 17 Jul 1996 02:44:25 -0700".to_string())
+        );
+        assert_eq!(
+            format_date(None, internal, "iso"),
+            Some(" 1996-07-17T02:44:25-07:00".to_string())
+        );
+        assert_eq!(
+            format_date(
+                Some(" Tue, 1 Jul 2003 10:52:37 +0200"),
+                internal,
+                "original"
+            ),
+            Some(" Tue, 1 Jul 2003 10:52:37 +0200".to_string())
+        );
+        assert_eq!(
+            format_date(Some(" garbage"), None, "iso"),
+            Some(" garbage".to_string())
+        );
+        assert_eq!(format_date(None, None, "original"), None);
+    }
+}
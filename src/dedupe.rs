@@ -0,0 +1,228 @@
+use crate::email_error::EmailError;
+use crate::export::header_value;
+use crate::helper::to_sequence_set;
+use crate::Server;
+use openssl::sha::sha256;
+use std::collections::{BTreeMap, HashMap};
+
+// How many messages are fetched per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+// The first pass only needs these, and the bodies are fetched later for the few
+// messages that have no Message-ID
+const HEADER_ITEMS: &str =
+    "UID RFC822.SIZE BODY.PEEK[HEADER.FIELDS (MESSAGE-ID FROM TO CC SUBJECT DATE)]";
+const HEADER_SECTION: &str = "BODY[HEADER.FIELDS (MESSAGE-ID FROM TO CC SUBJECT DATE)]";
+
+// Headers compared when a message has no Message-ID
+const FINGERPRINT_FIELDS: [&str; 5] = ["From", "To", "Cc", "Subject", "Date"];
+
+// One copy of a message, by the folder it is in and its UID there
+struct Found {
+    folder: String,
+    uid: u32,
+    size: u64,
+}
+
+// Copies that share a Message-ID, or failing that a hash of their content
+#[derive(Default)]
+struct Groups {
+    keys: Vec<String>,
+    copies: HashMap<String, Vec<Found>>,
+}
+
+impl Groups {
+    fn add(&mut self, key: String, found: Found) {
+        if !self.copies.contains_key(&key) {
+            self.keys.push(key.clone());
+        }
+        self.copies.entry(key).or_default().push(found);
+    }
+}
+
+impl<'a> Server<'a> {
+    // Finds messages that appear more than once across the folders and reports each
+    // group. The first copy found is kept, and with delete or a destination the others
+    // are deleted or moved away.
+    pub fn dedupe(
+        &mut self,
+        folders: &[String],
+        criteria: &str,
+        destination: &str,
+        delete: bool,
+        dry_run: bool,
+    ) -> Result<String, EmailError> {
+        let mut groups = Groups::default();
+        // Messages without a Message-ID, keyed by their normalized headers
+        let mut unidentified: HashMap<String, Vec<Found>> = HashMap::new();
+
+        for folder in folders {
+            self.select(folder)?;
+            let uids = self.uid_search(criteria)?;
+            for batch in uids.chunks(BATCH_MESSAGES) {
+                for fetch in self.uid_fetch(&to_sequence_set(batch), HEADER_ITEMS)? {
+                    let header = fetch.get(HEADER_SECTION).unwrap_or_default();
+                    let found = Found {
+                        folder: folder.clone(),
+                        uid: fetch.uid(),
+                        size: fetch.number("RFC822.SIZE"),
+                    };
+                    match header_value(header, "Message-ID").filter(|id| !id.is_empty()) {
+                        Some(id) => groups.add(format!("Message-ID {}", id), found),
+                        None => unidentified
+                            .entry(normalize_headers(header))
+                            .or_default()
+                            .push(found),
+                    }
+                }
+            }
+        }
+
+        // Only messages whose headers already match need their bodies compared
+        let mut to_hash: BTreeMap<String, BTreeMap<u32, (String, u64)>> = BTreeMap::new();
+        for (headers, copies) in unidentified {
+            if copies.len() < 2 {
+                continue;
+            }
+            for found in copies {
+                to_hash
+                    .entry(found.folder)
+                    .or_default()
+                    .insert(found.uid, (headers.clone(), found.size));
+            }
+        }
+        for folder in folders {
+            let Some(messages) = to_hash.get(folder) else {
+                continue;
+            };
+            let uids: Vec<u32> = messages.keys().copied().collect();
+            self.select(folder)?;
+            for batch in uids.chunks(BATCH_MESSAGES) {
+                for fetch in self.uid_fetch(&to_sequence_set(batch), "UID BODY.PEEK[TEXT]")? {
+                    let Some((headers, size)) = messages.get(&fetch.uid()) else {
+                        continue;
+                    };
+                    let mut content = headers.as_bytes().to_vec();
+                    content.extend_from_slice(&normalize_body(
+                        fetch.get("BODY[TEXT]").unwrap_or_default(),
+                    ));
+                    let found = Found {
+                        folder: folder.clone(),
+                        uid: fetch.uid(),
+                        size: *size,
+                    };
+                    groups.add(format!("SHA-256 {}", hex(&sha256(&content))), found);
+                }
+            }
+        }
+
+        let mut output = String::new();
+        let mut removals: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        let mut group_count = 0;
+        let mut extra_copies = 0;
+        let mut extra_bytes = 0;
+        for key in &groups.keys {
+            let copies = &groups.copies[key];
+            if copies.len() < 2 {
+                continue;
+            }
+            group_count += 1;
+            output.push_str(&format!("{}: {} copies\n", key, copies.len()));
+            output.push_str(&format!(
+                "  keep {} {}\n",
+                copies[0].folder.trim_matches('"'),
+                copies[0].uid
+            ));
+            for found in &copies[1..] {
+                extra_copies += 1;
+                extra_bytes += found.size;
+                // A copy already in the destination has nowhere to go
+                let action = if delete {
+                    "delete"
+                } else if !destination.is_empty() && found.folder != destination {
+                    "move"
+                } else {
+                    "duplicate"
+                };
+                if action != "duplicate" && !dry_run {
+                    removals
+                        .entry(found.folder.clone())
+                        .or_default()
+                        .push(found.uid);
+                }
+                output.push_str(&format!(
+                    "  {}{} {} {}\n",
+                    if dry_run && action != "duplicate" {
+                        "would "
+                    } else {
+                        ""
+                    },
+                    action,
+                    found.folder.trim_matches('"'),
+                    found.uid
+                ));
+            }
+        }
+        output.push_str(&format!(
+            "Found {} duplicate groups with {} extra copies ({} bytes)\n",
+            group_count, extra_copies, extra_bytes
+        ));
+
+        let mut removed = 0;
+        for (folder, uids) in &removals {
+            self.select(folder)?;
+            let uid_set = to_sequence_set(uids);
+            if delete {
+                self.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+                self.uid_expunge(&uid_set)?;
+            } else {
+                self.uid_move(&uid_set, destination)?;
+            }
+            removed += uids.len();
+        }
+        if delete && !dry_run {
+            output.push_str(&format!("Deleted {} duplicates\n", removed));
+        } else if !destination.is_empty() && !dry_run {
+            output.push_str(&format!(
+                "Moved {} duplicates to {}\n",
+                removed,
+                destination.trim_matches('"')
+            ));
+        }
+        Ok(output)
+    }
+}
+
+// Unfolded, lowercased and with runs of whitespace collapsed, so copies that were
+// rewrapped by different servers still compare equal
+fn normalize_headers(header: &[u8]) -> String {
+    FINGERPRINT_FIELDS
+        .iter()
+        .map(|name| {
+            let value = header_value(header, name).unwrap_or_default();
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            format!("{}: {}\n", name.to_lowercase(), value.to_lowercase())
+        })
+        .collect()
+}
+
+// Ignores line endings and trailing whitespace, which transports often change
+fn normalize_body(body: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(body.len());
+    for line in body.split(|&byte| byte == b'\n') {
+        let end = line
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(0, |position| position + 1);
+        normalized.extend_from_slice(&line[..end]);
+        normalized.push(b'\n');
+    }
+    while normalized.ends_with(b"\n\n") {
+        normalized.pop();
+    }
+    normalized
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,232 @@
+use crate::address::parse_addresses;
+use crate::email_error::EmailError;
+use crate::export::{header_value, write_atomically};
+use crate::helper::to_sequence_set;
+use crate::Server;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many messages are downloaded per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+// Where delivered messages end up locally
+pub enum Delivery {
+    // A procmail or maildrop style command, run through the shell with the message on
+    // its standard input. %F is replaced by the sender and %T by the recipient.
+    Mda(String),
+    Maildir(PathBuf),
+}
+
+impl Delivery {
+    pub fn new(mda: &str, maildir: &str) -> Option<Delivery> {
+        if !mda.is_empty() {
+            Some(Delivery::Mda(mda.to_string()))
+        } else if !maildir.is_empty() {
+            Some(Delivery::Maildir(PathBuf::from(maildir)))
+        } else {
+            None
+        }
+    }
+
+    // Only returns once the message is safely handed over, so the server copy can go
+    pub fn deliver(&self, message: &[u8], recipient: &str) -> Result<(), EmailError> {
+        match self {
+            Delivery::Mda(command) => {
+                let sender = header_value(message, "Return-Path")
+                    .or_else(|| header_value(message, "From"))
+                    .map(|value| first_address(&value))
+                    .unwrap_or_default();
+                let recipient = header_value(message, "Delivered-To")
+                    .or_else(|| header_value(message, "To"))
+                    .map(|value| first_address(&value))
+                    .filter(|address| !address.is_empty())
+                    .unwrap_or_else(|| recipient.to_string());
+                run_mda(&substitute(command, &sender, &recipient), message)
+            }
+            Delivery::Maildir(root) => {
+                for directory in ["cur", "new", "tmp"] {
+                    fs::create_dir_all(root.join(directory))
+                        .map_err(|_| EmailError::DeliveryFailure)?;
+                }
+                let path = root.join("new").join(unique_name());
+                write_atomically(root, &path, message).map_err(|_| EmailError::DeliveryFailure)
+            }
+        }
+    }
+}
+
+// What happens to the server copy once a message is delivered
+pub fn valid_after(after: &str) -> bool {
+    matches!(after, "" | "keep" | "seen" | "delete")
+}
+
+impl<'a> Server<'a> {
+    pub fn deliver(
+        &mut self,
+        criteria: &str,
+        delivery: &Delivery,
+        after: &str,
+    ) -> Result<String, EmailError> {
+        let uids = self.uid_search(criteria)?;
+        let mut delivered = 0;
+        let mut failed = 0;
+
+        for batch in uids.chunks(BATCH_MESSAGES) {
+            let fetches = self.uid_fetch(&to_sequence_set(batch), "UID BODY.PEEK[]")?;
+            // A message the server sent no body for fails rather than being delivered
+            // empty, and is left on the server
+            let mut messages = Vec::new();
+            for &uid in batch {
+                let message = fetches
+                    .iter()
+                    .filter(|fetch| fetch.uid() == uid)
+                    .find_map(|fetch| fetch.get("BODY[]"));
+                match message {
+                    Some(message) => messages.push((uid, message.to_vec())),
+                    None => {
+                        eprintln!("Could not deliver message {}", uid);
+                        failed += 1;
+                    }
+                }
+            }
+            let succeeded = self.deliver_messages(&messages, delivery);
+            self.after_delivery(&succeeded, after)?;
+            delivered += succeeded.len();
+            failed += messages.len() - succeeded.len();
+        }
+
+        Ok(format!(
+            "Delivered {} messages, {} failed\n",
+            delivered, failed
+        ))
+    }
+
+    // Delivers each message, returning the UIDs of the ones that made it
+    pub fn deliver_messages(
+        &mut self,
+        messages: &[(u32, Vec<u8>)],
+        delivery: &Delivery,
+    ) -> Vec<u32> {
+        let recipient = self.username().to_string();
+        let mut delivered = Vec::new();
+        for (uid, message) in messages {
+            match delivery.deliver(message, &recipient) {
+                Ok(_) => delivered.push(*uid),
+                Err(_) => eprintln!("Could not deliver message {}", uid),
+            }
+        }
+        delivered
+    }
+
+    // Marks or removes the server copies of delivered messages. A message that could not
+    // be delivered is never passed here and is left untouched for the next attempt.
+    pub fn after_delivery(&mut self, delivered: &[u32], after: &str) -> Result<(), EmailError> {
+        if delivered.is_empty() {
+            return Ok(());
+        }
+
+        let uid_set = to_sequence_set(delivered);
+        match after {
+            "seen" => {
+                self.uid_store(&uid_set, "+FLAGS.SILENT (\\Seen)")?;
+            }
+            "delete" => {
+                self.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+                self.uid_expunge(&uid_set)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn run_mda(command: &str, message: &[u8]) -> Result<(), EmailError> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|_| EmailError::DeliveryFailure)?;
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(message).is_ok());
+    // Only a zero exit status counts as delivered
+    match child.wait() {
+        Ok(status) if status.success() && written => Ok(()),
+        _ => Err(EmailError::DeliveryFailure),
+    }
+}
+
+// Replaces %F and %T with shell-quoted addresses, and %% with a single %
+fn substitute(command: &str, sender: &str, recipient: &str) -> String {
+    let mut result = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('F') => result.push_str(&shell_quote(sender)),
+            Some('T') => result.push_str(&shell_quote(recipient)),
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+    result
+}
+
+// Addresses come from the message, so they are never trusted by the shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Takes the address out of a header such as "Alice <alice@example.com>, bob@example.com"
+fn first_address(value: &str) -> String {
+    parse_addresses(value)
+        .first()
+        .map(|mailbox| mailbox.address())
+        .unwrap_or_default()
+}
+
+// Maildir names must be unique, as in time.M<microseconds>P<pid>Q<counter>.<host>
+fn unique_name() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "{}.M{}P{}Q{}.{}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst),
+        hostname()
+    )
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let length = buffer
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(buffer.len());
+    // Slashes and colons would break the file name and the info suffix
+    String::from_utf8_lossy(&buffer[..length])
+        .replace('/', "\\057")
+        .replace(':', "\\072")
+}
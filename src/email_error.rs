@@ -15,6 +15,31 @@ pub enum EmailError {
     SslError,
     HandshakeError,
     AddressNotFound,
+    CopyFailure,
+    CreateFailure,
+    StoreFailure,
+    AppendFailure,
+    FileNotFound,
+    EmptyMessage,
+    CheckpointFailure,
+    InvalidSearch,
+    ExportFailure,
+    CacheFailure,
+    FolderNotCached,
+    MessageNotCached,
+    OfflineUnsupported,
+    IdleFailure,
+    InvalidConfig,
+    LogFailure,
+    DeliveryFailure,
+    InvalidRules,
+    ListFailure,
+    JournalFailure,
+    OperationNotFound,
+    UndoFailure,
+    PartNotFound,
+    NoTextPart,
+    AttachmentFailure,
 }
 
 impl From<std::io::Error> for EmailError {
@@ -45,27 +70,60 @@ fn print_and_exit(message: &str, exit_code: i32) {
     std::process::exit(exit_code);
 }
 
-pub fn handle_error(err: EmailError) {
-    use EmailError::*;
+impl EmailError {
+    // The message shown for the error and the exit code it causes
+    pub fn details(&self) -> (&'static str, i32) {
+        use EmailError::*;
+
+        match self {
+            SafeConnection => ("Connection terminated safely", 1),
+            StreamNotConnected => ("Stream not connected", 1),
+            SslError => ("SSL error", 1),
+            HandshakeError => ("Handshake error", 1),
+            AddressNotFound => ("Could not make connection. Invalid address", 1),
+            InvalidArguments => ("Invalid CLI Arguments", 1),
+            SafeDisconnection => ("Server disconnected unexpectedly", 2),
+            LoginFailure => ("Login failure", 3),
+            MessageNotFound => ("Message not found", 3),
+            FolderNotFound => ("Folder not found", 3),
+            CopyFailure => ("Could not copy message to destination folder", 3),
+            CreateFailure => ("Could not create folder", 3),
+            StoreFailure => ("Could not update message flags", 3),
+            AppendFailure => ("Could not append message to folder", 3),
+            FileNotFound => ("Could not read input file", 1),
+            EmptyMessage => ("Message to append is empty", 1),
+            CheckpointFailure => ("Could not write checkpoint file", 1),
+            InvalidSearch => ("Invalid search criteria", 3),
+            ExportFailure => ("Could not write exported message", 1),
+            AttachmentFailure => ("Could not save attachment", 1),
+            CacheFailure => ("Could not access the local cache", 1),
+            FolderNotCached => ("Folder has not been synced to the local cache", 1),
+            MessageNotCached => ("Message is not in the local cache", 1),
+            OfflineUnsupported => ("Command is not available offline", 1),
+            IdleFailure => ("Server refused to IDLE", 3),
+            InvalidConfig => ("Could not read the account configuration", 1),
+            LogFailure => ("Could not open the log file", 1),
+            DeliveryFailure => ("Could not deliver message", 1),
+            InvalidRules => ("Could not read the rules file", 1),
+            ListFailure => ("Could not list folders", 3),
+            JournalFailure => ("Could not access the journal", 1),
+            OperationNotFound => ("No such operation to undo in the journal", 1),
+            UndoFailure => ("Folder has changed since the operation, cannot undo", 3),
+            InvalidHeader => ("Invalid Header", 4),
+            InvalidMimeVersion => ("Invalid MIME version. Expecting version 1.0", 4),
+            MimeMissing => ("Message does not contain MIME content", 4),
+            InvalidContentType => (
+                "Invalid Content-type header. Raw output needs multipart/alternative",
+                4,
+            ),
+            BoundaryParameterMissing => ("Boundary parameter value not present", 4),
+            PartNotFound => ("No such part in the message", 4),
+            NoTextPart => ("Message has no text part to show", 4),
+        }
+    }
+}
 
-    match err {
-        SafeConnection => print_and_exit("Connection terminated safely", 1),
-        StreamNotConnected => print_and_exit("Stream not connected", 1),
-        SslError => print_and_exit("SSL error", 1),
-        HandshakeError => print_and_exit("Handshake error", 1),
-        AddressNotFound => print_and_exit("Could not make connection. Invalid address", 1),
-        InvalidArguments => print_and_exit("Invalid CLI Arguments", 1),
-        SafeDisconnection => print_and_exit("Server disconnected unexpectedly", 2),
-        LoginFailure => print_and_exit("Login failure", 3),
-        MessageNotFound => print_and_exit("Message not found", 3),
-        FolderNotFound => print_and_exit("Folder not found", 3),
-        InvalidHeader => print_and_exit("Invalid Header", 4),
-        InvalidMimeVersion => print_and_exit("Invalid MIME version. Expecting version 1.0", 4),
-        MimeMissing => print_and_exit("Message does not contain MIME content", 4),
-        InvalidContentType => print_and_exit(
-            "Invalid Content-type header. Expecting multipart/alternative",
-            4,
-        ),
-        BoundaryParameterMissing => print_and_exit("Boundary parameter value not present", 4),
-    };
+pub fn handle_error(err: EmailError) {
+    let (message, exit_code) = err.details();
+    print_and_exit(message, exit_code);
 }
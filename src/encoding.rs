@@ -0,0 +1,248 @@
+// Undoes a Content-Transfer-Encoding (RFC 2045 section 6) a piece at a time, so large
+// bodies can be decoded as they are read. 7bit, 8bit, binary and anything unknown pass
+// through unchanged.
+pub enum Decoder {
+    QuotedPrintable(QuotedPrintable),
+    Base64(Base64),
+    Identity,
+}
+
+impl Decoder {
+    pub fn new(encoding: Option<&str>) -> Decoder {
+        match encoding.map(|encoding| encoding.trim().to_ascii_lowercase()) {
+            Some(encoding) if encoding == "quoted-printable" => {
+                Decoder::QuotedPrintable(QuotedPrintable::default())
+            }
+            Some(encoding) if encoding == "base64" => Decoder::Base64(Base64::default()),
+            _ => Decoder::Identity,
+        }
+    }
+
+    pub fn update(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        match self {
+            Decoder::QuotedPrintable(decoder) => decoder.update(input, output),
+            Decoder::Base64(decoder) => decoder.update(input, output),
+            Decoder::Identity => output.extend_from_slice(input),
+        }
+    }
+
+    // Decodes whatever is still held back once the input has ended
+    pub fn finish(&mut self, output: &mut Vec<u8>) {
+        match self {
+            Decoder::QuotedPrintable(decoder) => decoder.finish(output),
+            Decoder::Base64(decoder) => decoder.finish(output),
+            Decoder::Identity => (),
+        }
+    }
+}
+
+// Decodes a whole body at once
+pub fn decode(body: &[u8], encoding: Option<&str>) -> Vec<u8> {
+    let mut decoder = Decoder::new(encoding);
+    let mut output = Vec::with_capacity(body.len());
+    decoder.update(body, &mut output);
+    decoder.finish(&mut output);
+    output
+}
+
+// Quoted-printable works a line at a time, so a partial line is held back until the
+// rest of it arrives
+#[derive(Default)]
+pub struct QuotedPrintable {
+    line: Vec<u8>,
+}
+
+impl QuotedPrintable {
+    fn update(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        let mut rest = input;
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(&rest[..=end]);
+            decode_line(&self.line, output);
+            self.line.clear();
+            rest = &rest[end + 1..];
+        }
+        self.line.extend_from_slice(rest);
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        decode_line(&self.line, output);
+        self.line.clear();
+    }
+}
+
+// Decodes one line with its line break, if it has one. Whitespace at the end of a line
+// was added in transport and is dropped, and a final '=' is a soft line break, which
+// joins the line to the next. An '=' not followed by two hex digits is kept as it is.
+fn decode_line(line: &[u8], output: &mut Vec<u8>) {
+    let (content, line_break) = match line.strip_suffix(b"\r\n") {
+        Some(content) => (content, &b"\r\n"[..]),
+        None => match line.strip_suffix(b"\n") {
+            Some(content) => (content, &b"\n"[..]),
+            None => (line, &b""[..]),
+        },
+    };
+    let end = content
+        .iter()
+        .rposition(|&byte| byte != b' ' && byte != b'\t')
+        .map_or(0, |position| position + 1);
+    let content = &content[..end];
+    let (content, soft) = match content.strip_suffix(b"=") {
+        Some(content) => (content, true),
+        None => (content, false),
+    };
+
+    let mut index = 0;
+    while index < content.len() {
+        let byte = content[index];
+        let escaped = (byte == b'=')
+            .then(|| content.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|digits| {
+                let high = (digits[0] as char).to_digit(16)?;
+                let low = (digits[1] as char).to_digit(16)?;
+                Some((high * 16 + low) as u8)
+            });
+        match escaped {
+            Some(decoded) => {
+                output.push(decoded);
+                index += 3;
+            }
+            None => {
+                output.push(byte);
+                index += 1;
+            }
+        }
+    }
+    if !soft {
+        output.extend_from_slice(line_break);
+    }
+}
+
+// Base64 is read four characters at a time. Line breaks, whitespace and anything else
+// outside the alphabet are skipped, and padding that is missing or early is recovered
+// from by decoding whatever characters the last group has.
+#[derive(Default)]
+pub struct Base64 {
+    group: [u8; 4],
+    filled: usize,
+}
+
+impl Base64 {
+    fn update(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            if byte == b'=' {
+                // Padding ends a group, and some senders join encodings end to end
+                self.flush(output);
+                continue;
+            }
+            let Some(value) = base64_value(byte) else {
+                continue;
+            };
+            self.group[self.filled] = value;
+            self.filled += 1;
+            if self.filled == 4 {
+                self.flush(output);
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) {
+        self.flush(output);
+    }
+
+    // A group of two characters holds one byte and a group of three holds two, while
+    // a single character is not enough for a byte and is dropped
+    fn flush(&mut self, output: &mut Vec<u8>) {
+        let [a, b, c, d] = self.group.map(u32::from);
+        let bits = a << 18 | b << 12 | c << 6 | d;
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        let count = match self.filled {
+            4 => 3,
+            3 => 2,
+            2 => 1,
+            _ => 0,
+        };
+        output.extend_from_slice(&bytes[..count]);
+        self.group = [0; 4];
+        self.filled = 0;
+    }
+}
+
+fn base64_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qp(body: &[u8]) -> Vec<u8> {
+        decode(body, Some("Quoted-Printable"))
+    }
+
+    fn base64(body: &[u8]) -> Vec<u8> {
+        decode(body, Some(" BASE64 "))
+    }
+
+    // Decodes a byte at a time, as if every read ended mid-line or mid-group
+    fn byte_by_byte(body: &[u8], encoding: &str) -> Vec<u8> {
+        let mut decoder = Decoder::new(Some(encoding));
+        let mut output = Vec::new();
+        for byte in body {
+            decoder.update(&[*byte], &mut output);
+        }
+        decoder.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn decodes_quoted_printable_escapes_and_soft_breaks() {
+        assert_eq!(qp(b"caf=C3=A9 =3D ok\r\n"), "café = ok\r\n".as_bytes());
+        assert_eq!(qp(b"joined=\r\ntogether=\nhere"), b"joinedtogetherhere");
+        assert_eq!(qp(b"lower =c3=a9"), "lower é".as_bytes());
+    }
+
+    #[test]
+    fn drops_transport_padding_and_keeps_bad_escapes() {
+        assert_eq!(qp(b"trailing  \t\r\nsoft= \r\nend"), b"trailing\r\nsoftend");
+        assert_eq!(qp(b"100=% and =ZZ and =4"), b"100=% and =ZZ and =4");
+        assert_eq!(qp(b"="), b"");
+    }
+
+    #[test]
+    fn decodes_base64_with_whitespace_and_padding_recovery() {
+        assert_eq!(base64(b"aGVs\r\nbG8g\r\nd29y bGQ="), b"hello world");
+        assert_eq!(base64(b"aGk"), b"hi");
+        assert_eq!(base64(b"aGk=aGk="), b"hihi");
+        assert_eq!(base64(b"a*G!k="), b"hi");
+        assert_eq!(base64(b"a"), b"");
+    }
+
+    #[test]
+    fn decodes_the_same_whatever_the_chunk_size() {
+        let body = b"caf=C3=A9 soft=\r\nbreak  \r\n=3D\r\n";
+        assert_eq!(byte_by_byte(body, "quoted-printable"), qp(body));
+        let body = b"aGVs\r\nbG8g\r\nd29ybGQ";
+        assert_eq!(byte_by_byte(body, "base64"), base64(body));
+    }
+
+    #[test]
+    fn passes_other_encodings_through() {
+        for encoding in [
+            None,
+            Some("7bit"),
+            Some("8bit"),
+            Some("binary"),
+            Some("x-uue"),
+        ] {
+            assert_eq!(decode(b"=41 aGk=", encoding), b"=41 aGk=");
+        }
+    }
+}
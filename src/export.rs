@@ -0,0 +1,277 @@
+use crate::address::parse_addresses;
+use crate::checkpoint::Checkpoint;
+use crate::date::DateTime;
+use crate::email_error::EmailError;
+use crate::header::Header;
+use crate::helper::to_sequence_set;
+use crate::response::Fetch;
+use crate::Server;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many messages are downloaded per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+pub const DEFAULT_TEMPLATE: &str = "{uid}-{date}-{subject}.eml";
+
+impl<'a> Server<'a> {
+    pub fn export(
+        &mut self,
+        criteria: &str,
+        format: &str,
+        output: &str,
+        template: &str,
+        checkpoint_path: &str,
+    ) -> Result<String, EmailError> {
+        let mut target = ExportTarget::open(format, output, template)?;
+
+        let checkpoint_path = if checkpoint_path.is_empty() {
+            format!("{}.checkpoint", output.trim_end_matches('/'))
+        } else {
+            checkpoint_path.to_string()
+        };
+        let mut checkpoint = Checkpoint::open(&checkpoint_path)?;
+
+        // Keys include UIDVALIDITY, so a renumbered folder is exported again in full
+        let uid_validity = self.mailbox.uid_validity;
+        let key = |uid: u32| format!("{}:{}", uid_validity, uid);
+
+        let uids = self.uid_search(criteria)?;
+        let pending: Vec<u32> = uids
+            .iter()
+            .copied()
+            .filter(|&uid| !checkpoint.contains(&key(uid)))
+            .collect();
+        let skipped = uids.len() - pending.len();
+
+        let mut exported = 0;
+        for batch in pending.chunks(BATCH_MESSAGES) {
+            let items = "UID FLAGS INTERNALDATE BODY.PEEK[]";
+            let fetches = self.uid_fetch(&to_sequence_set(batch), items)?;
+
+            for fetch in &fetches {
+                // Unsolicited FETCH responses, such as flag updates, carry no message
+                let Some(message) = fetch.get("BODY[]") else {
+                    continue;
+                };
+                target.write(fetch, message, uid_validity)?;
+                checkpoint.record(&[key(fetch.uid())])?;
+                exported += 1;
+                eprint!("\rExported {}/{}", exported, pending.len());
+            }
+        }
+        if exported > 0 {
+            eprintln!();
+        }
+
+        Ok(format!(
+            "Exported {} messages to {}, skipped {} already exported\n",
+            exported, output, skipped
+        ))
+    }
+}
+
+enum ExportTarget {
+    Mbox(File),
+    Maildir(PathBuf),
+    Eml(PathBuf, String),
+}
+
+impl ExportTarget {
+    fn open(format: &str, output: &str, template: &str) -> Result<ExportTarget, EmailError> {
+        let target = match format {
+            "" | "mbox" => ExportTarget::Mbox(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(output)
+                    .map_err(|_| EmailError::ExportFailure)?,
+            ),
+            "maildir" => {
+                for directory in ["cur", "new", "tmp"] {
+                    fs::create_dir_all(Path::new(output).join(directory))
+                        .map_err(|_| EmailError::ExportFailure)?;
+                }
+                ExportTarget::Maildir(PathBuf::from(output))
+            }
+            "eml" => {
+                fs::create_dir_all(output).map_err(|_| EmailError::ExportFailure)?;
+                let template = if template.is_empty() {
+                    DEFAULT_TEMPLATE
+                } else {
+                    template
+                };
+                ExportTarget::Eml(PathBuf::from(output), template.to_string())
+            }
+            _ => return Err(EmailError::InvalidArguments),
+        };
+        Ok(target)
+    }
+
+    fn write(
+        &mut self,
+        fetch: &Fetch,
+        message: &[u8],
+        uid_validity: u32,
+    ) -> Result<(), EmailError> {
+        let date = DateTime::from_internal_date(&fetch.text("INTERNALDATE"));
+
+        match self {
+            ExportTarget::Mbox(file) => {
+                let from_line = format!(
+                    "From MAILER-DAEMON {}\n",
+                    date.map(|date| date.asctime())
+                        .unwrap_or_else(|| "Thu Jan  1 00:00:00 1970".to_string())
+                );
+                let mut entry = from_line.into_bytes();
+                entry.extend_from_slice(&mboxrd_quote(message));
+                entry.push(b'\n');
+                file.write_all(&entry)
+                    .and_then(|_| file.flush())
+                    .map_err(|_| EmailError::ExportFailure)
+            }
+            ExportTarget::Maildir(root) => {
+                let name = format!(
+                    "{}.V{}U{}.fetchmail:2,{}",
+                    date.map_or(0, |date| date.timestamp()),
+                    uid_validity,
+                    fetch.uid(),
+                    maildir_info(&fetch.flags())
+                );
+                write_atomically(root, &root.join("cur").join(name), message)
+            }
+            ExportTarget::Eml(root, template) => {
+                let name = fill_template(template, fetch.uid(), date.as_ref(), message);
+                let path = unused_path(&root.join(name));
+                fs::write(path, message).map_err(|_| EmailError::ExportFailure)
+            }
+        }
+    }
+}
+
+// Writes into tmp first and renames into place once on disk, so readers never see
+// partial files
+pub fn write_atomically(root: &Path, path: &Path, contents: &[u8]) -> Result<(), EmailError> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    let temporary = root
+        .join("tmp")
+        .join(format!("{}.{}", stamp, std::process::id()));
+
+    File::create(&temporary)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|_| EmailError::ExportFailure)
+}
+
+// Converts line endings to \n and quotes From_ lines the mboxrd way, so >From becomes >>From
+fn mboxrd_quote(message: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(message.len());
+    for line in message.split_inclusive(|&byte| byte == b'\n') {
+        let line = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(line);
+        let quotes = line.iter().take_while(|&&byte| byte == b'>').count();
+        if line[quotes..].starts_with(b"From ") {
+            quoted.push(b'>');
+        }
+        quoted.extend_from_slice(line);
+        quoted.push(b'\n');
+    }
+    quoted
+}
+
+// Maildir flag letters must be in ASCII order
+pub fn maildir_info(flags: &[String]) -> String {
+    let mut letters: Vec<char> = flags
+        .iter()
+        .filter_map(|flag| match flag.to_lowercase().as_str() {
+            "\\draft" => Some('D'),
+            "\\flagged" => Some('F'),
+            "\\answered" => Some('R'),
+            "\\seen" => Some('S'),
+            "\\deleted" => Some('T'),
+            _ => None,
+        })
+        .collect();
+    letters.sort_unstable();
+    letters.into_iter().collect()
+}
+
+fn fill_template(template: &str, uid: u32, date: Option<&DateTime>, message: &[u8]) -> String {
+    let subject = header_value(message, "Subject").unwrap_or_default();
+    let from = header_value(message, "From")
+        .and_then(|value| {
+            parse_addresses(&value)
+                .first()
+                .map(|mailbox| mailbox.address())
+        })
+        .unwrap_or_default();
+    let name = template
+        .replace("{uid}", &uid.to_string())
+        .replace(
+            "{date}",
+            &date.map(|date| date.iso_date()).unwrap_or_default(),
+        )
+        .replace("{subject}", &shorten(&sanitize(&subject)))
+        .replace("{from}", &shorten(&sanitize(&from)));
+    // A name that would be empty or only an extension, such as {subject}.eml without a
+    // subject, is replaced so every message still gets a file of its own
+    if name.trim().starts_with('.') || sanitize(&name).is_empty() {
+        return format!("{}.eml", uid);
+    }
+    sanitize(&name)
+}
+
+// Keeps file names to one path component of printable characters
+pub fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    cleaned.trim_start_matches('.').trim().to_string()
+}
+
+// Cuts a value to at most 100 bytes on a character boundary, so names built from a few
+// of them stay within the 255 bytes file systems allow
+pub fn shorten(value: &str) -> String {
+    let mut end = value.len().min(100);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].trim_end().to_string()
+}
+
+// Appends a counter rather than overwrite an existing file
+fn unused_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("message");
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    (1..)
+        .map(|n| match extension {
+            Some(extension) => path.with_file_name(format!("{}-{}.{}", stem, n, extension)),
+            None => path.with_file_name(format!("{}-{}", stem, n)),
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// Finds a header field in a raw message and unfolds it onto one line
+pub fn header_value(message: &[u8], name: &str) -> Option<String> {
+    Header::parse(message)
+        .get(name)
+        .map(|value| value.trim().to_string())
+}
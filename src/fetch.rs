@@ -1,6 +1,7 @@
 use crate::email_error::EmailError;
 use crate::file;
 use crate::helper::{carriage_return, parse_string};
+use crate::response::parse_fetches;
 use crate::Server;
 
 impl<'a> Server<'a> {
@@ -13,13 +14,35 @@ impl<'a> Server<'a> {
             if self.debug {
                 file::write(&carriage_response)?;
             }
-            return Ok(carriage_response);
+            Ok(carriage_response)
         } else {
             if self.debug {
                 file::write(&response)?;
             }
 
+            Err(EmailError::MessageNotFound)
+        }
+    }
+
+    // The message exactly as the server holds it, for commands that parse its structure
+    pub fn fetch_bytes(&mut self, message_num: u32) -> Result<Vec<u8>, EmailError> {
+        let command = format!("FETCH {} BODY.PEEK[]", message_num);
+        let response = self.run_command_bytes(&command)?;
+        if !self.valid_response_bytes(&response) {
             return Err(EmailError::MessageNotFound);
         }
+        parse_fetches(&response)
+            .into_iter()
+            .find_map(|mut fetch| fetch.take("BODY[]"))
+            .ok_or(EmailError::MessageNotFound)
     }
 }
+
+// Formats a raw message the way the FETCH response above is formatted
+pub fn format_message(message: &[u8]) -> String {
+    let text = String::from_utf8_lossy(message);
+    let mut lines: Vec<&str> = text.split("\r\n").collect();
+    // The final line shares its response line with the closing parenthesis and is dropped
+    lines.pop();
+    carriage_return(&lines.join("\n"))
+}
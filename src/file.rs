@@ -5,4 +5,4 @@ pub fn write(response: &str) -> std::io::Result<()> {
     let mut file = File::create("test/output.txt")?;
     file.write_all(response.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
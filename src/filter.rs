@@ -0,0 +1,137 @@
+use crate::deliver::Delivery;
+use crate::email_error::EmailError;
+use crate::helper::to_sequence_set;
+use crate::rules::{evaluate, needs_body, Action, Candidate, Rule};
+use crate::Server;
+use std::collections::{BTreeMap, HashMap};
+
+// How many messages are fetched per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+impl<'a> Server<'a> {
+    pub fn filter(
+        &mut self,
+        rules: &[Rule],
+        criteria: &str,
+        dry_run: bool,
+    ) -> Result<String, EmailError> {
+        let uids = self.uid_search(criteria)?;
+        self.apply_rules(rules, &uids, dry_run)
+    }
+
+    // Runs the rules over the given messages in the selected folder, printing a line per
+    // action. A dry run only prints what would be done.
+    pub fn apply_rules(
+        &mut self,
+        rules: &[Rule],
+        uids: &[u32],
+        dry_run: bool,
+    ) -> Result<String, EmailError> {
+        let (items, section) = if needs_body(rules) {
+            ("UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[]", "BODY[]")
+        } else {
+            (
+                "UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER]",
+                "BODY[HEADER]",
+            )
+        };
+        let mut output = String::new();
+
+        for batch in uids.chunks(BATCH_MESSAGES) {
+            let mut plan = Plan::default();
+            for fetch in self.uid_fetch(&to_sequence_set(batch), items)? {
+                // Unsolicited FETCH responses carry flags only and are not rule candidates
+                let Some(message) = fetch.get(section) else {
+                    continue;
+                };
+                if !batch.contains(&fetch.uid()) {
+                    continue;
+                }
+                let flags = fetch.flags();
+                let internal_date = fetch.text("INTERNALDATE");
+                let candidate = Candidate {
+                    flags: &flags,
+                    internal_date: &internal_date,
+                    size: fetch.number("RFC822.SIZE"),
+                    message,
+                };
+
+                for (rule, action) in evaluate(rules, &candidate) {
+                    output.push_str(&format!(
+                        "{} {}: {}{}\n",
+                        fetch.uid(),
+                        rule,
+                        if dry_run { "would " } else { "" },
+                        action.describe()
+                    ));
+                    if dry_run {
+                        continue;
+                    }
+                    if let Action::Pipe(command) = &action {
+                        let delivery = Delivery::Mda(command.to_string());
+                        if delivery.deliver(message, self.username()).is_err() {
+                            eprintln!("Could not pipe message {} to {}", fetch.uid(), command);
+                        }
+                    }
+                    plan.add(fetch.uid(), action);
+                }
+            }
+            self.carry_out(plan)?;
+        }
+
+        Ok(output)
+    }
+
+    // Runs the plan a step at a time, with one command for each action in a step
+    fn carry_out(&mut self, plan: Plan) -> Result<(), EmailError> {
+        for step in &plan.steps {
+            for (action, uids) in step {
+                let uid_set = to_sequence_set(uids);
+                match action {
+                    Action::Flag(flag) => {
+                        self.uid_store(&uid_set, &format!("+FLAGS.SILENT ({})", flag))?;
+                    }
+                    Action::Unflag(flag) => {
+                        self.uid_store(&uid_set, &format!("-FLAGS.SILENT ({})", flag))?;
+                    }
+                    Action::Copy(folder) => {
+                        self.uid_copy(&uid_set, folder)?;
+                    }
+                    Action::Move(folder) => {
+                        self.uid_move(&uid_set, folder)?;
+                    }
+                    Action::Delete => {
+                        self.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+                        self.uid_expunge(&uid_set)?;
+                    }
+                    Action::Pipe(_) | Action::Stop => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The actions for a batch of messages. Each message's actions run in the order its rules
+// give them, so the first action of every message goes in the first step, the second in
+// the next, and so on. Within a step the same action on several messages becomes a
+// single command. Pipes have already run and stops are not actions on the server.
+#[derive(Default)]
+struct Plan {
+    steps: Vec<BTreeMap<Action, Vec<u32>>>,
+    next_step: HashMap<u32, usize>,
+}
+
+impl Plan {
+    fn add(&mut self, uid: u32, action: Action) {
+        if matches!(action, Action::Pipe(_) | Action::Stop) {
+            return;
+        }
+        let step = self.next_step.entry(uid).or_default();
+        if self.steps.len() <= *step {
+            self.steps.push(BTreeMap::new());
+        }
+        self.steps[*step].entry(action).or_default().push(uid);
+        *step += 1;
+    }
+}
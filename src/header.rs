@@ -0,0 +1,238 @@
+use crate::charset;
+
+// A message header as an ordered list of fields. Repeated fields such as Received or
+// Comments are all kept, in the order they appear.
+pub struct Header {
+    fields: Vec<Field>,
+}
+
+pub struct Field {
+    // As written, without any whitespace before the colon
+    pub name: String,
+    // Everything after the colon with the line breaks of folding removed, so the
+    // whitespace that followed each break is kept as it was (RFC 5322 section 2.2.3)
+    pub value: String,
+}
+
+impl Header {
+    // Reads fields up to the first empty line, which ends the header. Bare LF line
+    // endings are accepted, as is the obsolete syntax of whitespace before the colon
+    // (RFC 5322 section 4.5). Lines that are not fields, such as an mbox From_ line,
+    // are skipped, and so are continuation lines with no field to belong to.
+    pub fn parse(block: &[u8]) -> Header {
+        let text = String::from_utf8_lossy(block);
+        let mut fields: Vec<Field> = Vec::new();
+        let mut continuing = false;
+
+        for line in text.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                break;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                if let Some(field) = fields.last_mut().filter(|_| continuing) {
+                    field.value.push_str(line);
+                }
+                continue;
+            }
+
+            continuing = false;
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let name = name.trim_end_matches([' ', '\t']);
+            if !valid_name(name) {
+                continue;
+            }
+            fields.push(Field {
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+            continuing = true;
+        }
+
+        Header { fields }
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    // The first field with the name, compared case-insensitively
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+            .map(|field| field.value.as_str())
+    }
+
+    // Every field with the name, in order
+    pub fn get_all<'h>(&'h self, name: &'h str) -> impl Iterator<Item = &'h str> + 'h {
+        self.fields
+            .iter()
+            .filter(move |field| field.name.eq_ignore_ascii_case(name))
+            .map(|field| field.value.as_str())
+    }
+}
+
+// Field names are printable US-ASCII other than the colon (RFC 5322 section 3.6.8)
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| (33..=126).contains(&byte) && byte != b':')
+}
+
+// Decodes RFC 2047 encoded words such as =?ISO-8859-1?Q?Caf=E9?= in a field value.
+// Whitespace between two adjacent encoded words is dropped, and the bytes of adjacent
+// words in the same charset are joined before decoding, since a character may be split
+// across them. Anything malformed, or in an unknown charset, is left as written.
+pub fn decode_words(value: &str) -> String {
+    let mut output = String::new();
+    let mut pending = Pending::default();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("=?") {
+        let before = &rest[..start];
+        let Some((word, length)) = EncodedWord::parse(&rest[start..]) else {
+            pending.flush(&mut output);
+            output.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        };
+
+        let adjacent = !pending.raw.is_empty() && before.chars().all(|c| c == ' ' || c == '\t');
+        if !adjacent {
+            pending.flush(&mut output);
+            output.push_str(before);
+            pending.charset = word.charset;
+        } else if !pending.charset.eq_ignore_ascii_case(&word.charset) {
+            pending.flush(&mut output);
+            pending.charset = word.charset;
+        } else {
+            pending.raw.push_str(before);
+        }
+        pending.bytes.extend(word.bytes);
+        pending.raw.push_str(&rest[start..start + length]);
+        rest = &rest[start + length..];
+    }
+
+    pending.flush(&mut output);
+    output.push_str(rest);
+    output
+}
+
+// Encoded words waiting to be decoded together, with how they were written in case
+// their charset is unknown
+#[derive(Default)]
+struct Pending {
+    charset: String,
+    bytes: Vec<u8>,
+    raw: String,
+}
+
+impl Pending {
+    fn flush(&mut self, output: &mut String) {
+        if self.raw.is_empty() {
+            return;
+        }
+        match charset::decode(&self.bytes, &self.charset) {
+            Some(text) => output.push_str(&text),
+            None => output.push_str(&self.raw),
+        }
+        self.bytes.clear();
+        self.raw.clear();
+    }
+}
+
+struct EncodedWord {
+    charset: String,
+    bytes: Vec<u8>,
+}
+
+impl EncodedWord {
+    // Parses =?charset?encoding?text?= at the start of the input, returning the word
+    // and how many bytes it took up
+    fn parse(input: &str) -> Option<(EncodedWord, usize)> {
+        let inner = input.strip_prefix("=?")?;
+        let (charset, inner) = inner.split_once('?')?;
+        let (encoding, inner) = inner.split_once('?')?;
+        let end = inner.find("?=")?;
+        let text = &inner[..end];
+        if charset.is_empty() || text.contains([' ', '\t', '?']) {
+            return None;
+        }
+
+        let bytes = match encoding {
+            "B" | "b" => openssl::base64::decode_block(text).ok()?,
+            "Q" | "q" => decode_q(text)?,
+            _ => return None,
+        };
+        // A language may follow the charset, as in UTF-8*en (RFC 2231 section 5)
+        let charset = charset.split('*').next().unwrap_or_default().to_string();
+        let length = input.len() - inner.len() + end + 2;
+        Some((EncodedWord { charset, bytes }, length))
+    }
+}
+
+// Q is quoted-printable with '_' standing for a space
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                bytes.push((high * 16 + low) as u8);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_b_and_q_words() {
+        assert_eq!(decode_words("=?UTF-8?B?5pel5pys6Kqe?="), "日本語");
+        assert_eq!(decode_words("=?ISO-8859-1?Q?Caf=E9?="), "Café");
+        assert_eq!(decode_words("=?iso-8859-1?q?a_b?="), "a b");
+        assert_eq!(decode_words("=?UTF-8*en?Q?hi?="), "hi");
+    }
+
+    #[test]
+    fn drops_whitespace_between_adjacent_words_only() {
+        assert_eq!(decode_words("=?UTF-8?Q?a?= \t =?UTF-8?Q?b?="), "ab");
+        assert_eq!(decode_words("=?UTF-8?Q?a?= =?ISO-8859-1?Q?b?="), "ab");
+        assert_eq!(decode_words("=?UTF-8?Q?a?= and =?UTF-8?Q?b?="), "a and b");
+        assert_eq!(decode_words("Re: =?UTF-8?Q?a?= x"), "Re: a x");
+    }
+
+    #[test]
+    fn joins_a_character_split_across_adjacent_words() {
+        // 日 is E6 97 A5, split after its first byte
+        assert_eq!(decode_words("=?UTF-8?Q?=E6?= =?UTF-8?Q?=97=A5?="), "日");
+    }
+
+    #[test]
+    fn leaves_malformed_and_unknown_words_as_written() {
+        for value in [
+            "=?UTF-8?X?abc?=",
+            "=?UTF-8?Q?a b?=",
+            "=?UTF-8?Q?=4?=",
+            "=??Q?abc?=",
+            "=?UTF-8?B?abc",
+            "50% =? off",
+            "=?x-unknown?Q?abc?=",
+        ] {
+            assert_eq!(decode_words(value), value);
+        }
+        assert_eq!(decode_words("=?=?UTF-8?Q?a?="), "=?a");
+    }
+}
@@ -1,7 +1,7 @@
 pub fn parse_string(input: &str, remove_start: usize, remove_end: usize) -> String {
     // Split the input string into lines
     let mut lines: Vec<&str> = input.split("\r\n").collect();
-    let total_lines = lines.len() as usize;
+    let total_lines = lines.len();
 
     // Calculate the number of lines to remove from the start and end
     let start_to_remove = remove_start.min(total_lines);
@@ -19,4 +19,183 @@ pub fn parse_string(input: &str, remove_start: usize, remove_end: usize) -> Stri
 
 pub fn carriage_return(input: &str) -> String {
     input.replace("\n", "\r\n")
-}
\ No newline at end of file
+}
+// Finds the value following an attribute name in an untagged FETCH line, e.g. UID 42
+pub fn fetch_attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let mut words = line
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|word| !word.is_empty());
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case(name) {
+            return words.next();
+        }
+    }
+    None
+}
+
+// Returns the arguments of a bracketed response code such as [COPYUID 38505 304 3956]
+pub fn response_code<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = response;
+    while let Some(start) = rest.find('[') {
+        let after = &rest[start + 1..];
+        let end = after.find(']')?;
+        let code = &after[..end];
+        let (code_name, arguments) = code.split_once(' ').unwrap_or((code, ""));
+        if code_name.eq_ignore_ascii_case(name) {
+            return Some(arguments.trim());
+        }
+        rest = &after[end + 1..];
+    }
+    None
+}
+
+// A sequence set such as 1:3,7, kept as its ranges in the order given so that a set
+// as large as 1:4294967295 costs no more to hold than a small one
+pub struct SequenceSet {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl SequenceSet {
+    // Reads the set with * standing for the last number given, skipping anything invalid
+    pub fn parse(set: &str, last: u32) -> SequenceSet {
+        let number = |value: &str| {
+            if value == "*" {
+                Some(last)
+            } else {
+                value.parse::<u32>().ok()
+            }
+        };
+        let ranges = set
+            .split(',')
+            .filter_map(|range| match range.split_once(':') {
+                Some((start, end)) => {
+                    let (start, end) = (number(start)?, number(end)?);
+                    Some((start.min(end), start.max(end)))
+                }
+                None => number(range).map(|number| (number, number)),
+            })
+            .collect();
+        SequenceSet { ranges }
+    }
+
+    pub fn contains(&self, number: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&number))
+    }
+
+    // How far into the set a number is, counting in the order the set lists them
+    pub fn position(&self, number: u32) -> Option<u64> {
+        let mut before = 0;
+        for &(start, end) in &self.ranges {
+            if (start..=end).contains(&number) {
+                return Some(before + u64::from(number - start));
+            }
+            before += u64::from(end - start) + 1;
+        }
+        None
+    }
+
+    // The number at a position in the set
+    pub fn nth(&self, mut index: u64) -> Option<u32> {
+        for &(start, end) in &self.ranges {
+            let length = u64::from(end - start) + 1;
+            if index < length {
+                return Some(start + index as u32);
+            }
+            index -= length;
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ranges.iter().flat_map(|&(start, end)| start..=end)
+    }
+}
+
+// Converts bare \n line endings to \r\n, leaving existing \r\n pairs untouched
+pub fn crlf_line_endings(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut prev_byte = None;
+    for &byte in input {
+        if byte == b'\n' && prev_byte != Some(b'\r') {
+            output.push(b'\r');
+        }
+        output.push(byte);
+        prev_byte = Some(byte);
+    }
+    output
+}
+
+// Returns the length of the literal announced at the end of a line, e.g. {1024}\r\n
+pub fn literal_length(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\r\n")?.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|&byte| byte == b'{')?;
+    let digits = line[start + 1..]
+        .strip_suffix(b"+")
+        .unwrap_or(&line[start + 1..]);
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+// Compresses a list of numbers into a sequence set, joining consecutive runs
+pub fn to_sequence_set(numbers: &[u32]) -> String {
+    let mut sorted = numbers.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < sorted.len() {
+        let start = sorted[i];
+        while i + 1 < sorted.len() && sorted[i + 1] == sorted[i] + 1 {
+            i += 1;
+        }
+        if sorted[i] == start {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}:{}", start, sorted[i]));
+        }
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_set_keeps_ranges_without_expanding_them() {
+        let set = SequenceSet::parse("1:4294967295", 0);
+        assert!(set.contains(1));
+        assert!(set.contains(u32::MAX));
+        assert_eq!(set.position(u32::MAX), Some(u64::from(u32::MAX) - 1));
+        assert_eq!(set.nth(u64::from(u32::MAX) - 1), Some(u32::MAX));
+        assert_eq!(set.nth(u64::from(u32::MAX)), None);
+    }
+
+    #[test]
+    fn sequence_set_counts_positions_in_listed_order() {
+        let set = SequenceSet::parse("5:7,2,10:9", 0);
+        assert_eq!(set.iter().collect::<Vec<u32>>(), [5, 6, 7, 2, 9, 10]);
+        assert_eq!(set.position(2), Some(3));
+        assert_eq!(set.position(10), Some(5));
+        assert_eq!(set.position(8), None);
+        assert_eq!(set.nth(4), Some(9));
+    }
+
+    #[test]
+    fn sequence_set_reads_star_as_last_and_skips_invalid_ranges() {
+        let set = SequenceSet::parse("3:*,x,4:y,,8", 6);
+        assert_eq!(set.iter().collect::<Vec<u32>>(), [3, 4, 5, 6, 8]);
+        assert!(!set.contains(7));
+        assert_eq!(SequenceSet::parse("", 6).iter().count(), 0);
+    }
+
+    #[test]
+    fn to_sequence_set_joins_consecutive_runs() {
+        assert_eq!(to_sequence_set(&[5, 1, 2, 3, 9, 3, 10]), "1:3,5,9:10");
+        assert_eq!(to_sequence_set(&[7]), "7");
+        assert_eq!(to_sequence_set(&[]), "");
+    }
+}
@@ -0,0 +1,752 @@
+// Renders an HTML body as plain text for reading in a terminal. Scripts, styles and the
+// head are dropped, block elements become paragraphs, lists get bullets or numbers,
+// block quotes are marked with "> " and links are shown as "text [n]" with the URLs
+// listed as footnotes at the end. Lines are wrapped to the width given, or not at all
+// for a width of 0.
+pub const DEFAULT_WIDTH: usize = 78;
+
+pub fn render(html: &str, width: usize) -> String {
+    let mut renderer = Renderer {
+        width,
+        lines: Vec::new(),
+        text: String::new(),
+        blocks: Vec::new(),
+        marker: None,
+        blank_pending: false,
+        skip: 0,
+        pre: 0,
+        link: None,
+        links: Vec::new(),
+    };
+    for token in tokenize(html) {
+        renderer.token(token);
+    }
+    renderer.finish()
+}
+
+// Elements whose contents are not shown
+const HIDDEN: [&str; 6] = ["head", "title", "script", "style", "template", "svg"];
+
+enum Token {
+    Text(String),
+    Start(String, Vec<(String, String)>),
+    End(String),
+}
+
+enum Block {
+    Quote,
+    // Whether the list is numbered, the number of the next item, and how far items
+    // are indented, which is the width of their marker
+    List {
+        ordered: bool,
+        next: u32,
+        indent: usize,
+    },
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<String>,
+    // Text of the paragraph being built, with whitespace collapsed and a '\n' for each
+    // line break
+    text: String,
+    blocks: Vec<Block>,
+    // The bullet or number for the first line of a list item
+    marker: Option<String>,
+    blank_pending: bool,
+    skip: usize,
+    pre: usize,
+    // The target of the link being read, and where its text starts
+    link: Option<(String, usize)>,
+    links: Vec<String>,
+}
+
+impl Renderer {
+    fn token(&mut self, token: Token) {
+        match token {
+            Token::Text(text) => self.add_text(&text),
+            Token::Start(name, attributes) => self.start(&name, &attributes),
+            Token::End(name) => self.end(&name),
+        }
+    }
+
+    fn add_text(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+        let text = decode_entities(text);
+        if self.pre > 0 {
+            self.text.push_str(&text.replace('\r', ""));
+            return;
+        }
+        for c in text.chars() {
+            if is_space(c) {
+                if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+                    self.text.push(' ');
+                }
+            } else {
+                self.text.push(c);
+            }
+        }
+    }
+
+    fn start(&mut self, name: &str, attributes: &[(String, String)]) {
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        if HIDDEN.contains(&name) {
+            self.skip += 1;
+            return;
+        }
+        // Anything left open in the head, such as an unclosed title, ends here
+        if name == "body" {
+            self.skip = 0;
+        }
+        if self.skip > 0 {
+            return;
+        }
+
+        match name {
+            "br" => {
+                let trimmed = self.text.trim_end_matches(' ').len();
+                self.text.truncate(trimmed);
+                self.text.push('\n');
+            }
+            "hr" => {
+                self.paragraph();
+                let width = if self.width == 0 {
+                    40
+                } else {
+                    self.width.min(40)
+                };
+                self.lines.push("-".repeat(width));
+            }
+            "pre" => {
+                self.paragraph();
+                self.pre += 1;
+            }
+            "blockquote" => {
+                self.paragraph();
+                self.blocks.push(Block::Quote);
+            }
+            "ul" | "ol" => {
+                // A list inside a list item follows straight on from the item's text
+                if self.in_list() {
+                    self.flush();
+                } else {
+                    self.paragraph();
+                }
+                self.blocks.push(Block::List {
+                    ordered: name == "ol",
+                    next: attribute("start")
+                        .and_then(|start| start.trim().parse().ok())
+                        .unwrap_or(1),
+                    indent: 0,
+                });
+            }
+            "li" => {
+                self.flush();
+                if let Some(Block::List {
+                    ordered,
+                    next,
+                    indent,
+                }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::List { .. }))
+                {
+                    let marker = if *ordered {
+                        let number = *next;
+                        // Numbering stops at u32::MAX rather than overflowing
+                        *next = next.saturating_add(1);
+                        format!("{}. ", number)
+                    } else {
+                        "* ".to_string()
+                    };
+                    *indent = marker.chars().count();
+                    self.marker = Some(marker);
+                }
+            }
+            "tr" => self.flush(),
+            "td" | "th" if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) => {
+                self.text.push(' ');
+            }
+            "img" => {
+                if let Some(alt) = attribute("alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.add_text(&format!(" [{}] ", alt.trim()));
+                }
+            }
+            "a" => {
+                self.link =
+                    attribute("href").map(|href| (href.trim().to_string(), self.text.len()));
+            }
+            name if is_block(name) => self.paragraph(),
+            _ => (),
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if HIDDEN.contains(&name) {
+            self.skip = self.skip.saturating_sub(1);
+            return;
+        }
+        if self.skip > 0 {
+            return;
+        }
+
+        match name {
+            "pre" => {
+                self.flush();
+                self.pre = self.pre.saturating_sub(1);
+                self.blank_pending = true;
+            }
+            "blockquote" => {
+                self.paragraph();
+                if let Some(position) = self
+                    .blocks
+                    .iter()
+                    .rposition(|block| matches!(block, Block::Quote))
+                {
+                    self.blocks.remove(position);
+                }
+            }
+            "ul" | "ol" => {
+                self.flush();
+                if let Some(position) = self
+                    .blocks
+                    .iter()
+                    .rposition(|block| matches!(block, Block::List { .. }))
+                {
+                    self.blocks.remove(position);
+                }
+                if !self.in_list() {
+                    self.blank_pending = true;
+                }
+            }
+            "li" | "tr" => self.flush(),
+            "a" => self.end_link(),
+            name if is_block(name) => self.paragraph(),
+            _ => (),
+        }
+    }
+
+    // Numbers the link unless it goes nowhere useful, or its text already shows where
+    fn end_link(&mut self) {
+        let Some((href, start)) = self.link.take() else {
+            return;
+        };
+        let text = self
+            .text
+            .get(start.min(self.text.len())..)
+            .unwrap_or_default()
+            .trim();
+        let target = href.strip_prefix("mailto:").unwrap_or(&href);
+        if text.is_empty()
+            || href.is_empty()
+            || href.starts_with('#')
+            || href.to_ascii_lowercase().starts_with("javascript:")
+            || text == target
+        {
+            return;
+        }
+        let number = match self.links.iter().position(|link| *link == href) {
+            Some(index) => index + 1,
+            None => {
+                self.links.push(href);
+                self.links.len()
+            }
+        };
+        let trimmed = self.text.trim_end_matches(' ').len();
+        self.text.truncate(trimmed);
+        self.text.push_str(&format!(" [{}]", number));
+    }
+
+    fn in_list(&self) -> bool {
+        self.blocks
+            .iter()
+            .any(|block| matches!(block, Block::List { .. }))
+    }
+
+    // Ends the paragraph, with a blank line before whatever comes next
+    fn paragraph(&mut self) {
+        self.flush();
+        self.blank_pending = true;
+    }
+
+    // Writes out the text so far as wrapped lines
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        let preformatted = self.pre > 0;
+        let text = if preformatted {
+            text.strip_prefix('\n')
+                .unwrap_or(&text)
+                .trim_end()
+                .to_string()
+        } else {
+            text.trim_matches([' ', '\n']).to_string()
+        };
+        if text.is_empty() && self.marker.is_none() {
+            return;
+        }
+
+        // Blank lines inside a quote are still marked as quoted
+        if self.blank_pending && !self.lines.is_empty() {
+            let prefix = self.prefix(None);
+            let blank = prefix.trim_end();
+            let quoted = self
+                .lines
+                .last()
+                .is_some_and(|line| line.starts_with(blank));
+            self.lines.push(if quoted { blank } else { "" }.to_string());
+        }
+        self.blank_pending = false;
+
+        let marker = self.marker.take();
+        let mut first = self.prefix(marker.as_deref());
+        let rest = self.prefix(None);
+        let width = self.width.saturating_sub(rest.chars().count()).max(20);
+        for segment in text.split('\n') {
+            let lines = if preformatted || self.width == 0 {
+                vec![segment.to_string()]
+            } else {
+                wrap(segment.trim(), width)
+            };
+            for line in lines {
+                let prefix = std::mem::replace(&mut first, rest.clone());
+                self.lines
+                    .push(format!("{}{}", prefix, line).trim_end().to_string());
+            }
+        }
+    }
+
+    // What starts each line at the current depth of quotes and lists. The first line
+    // of a list item has its marker in place of the innermost list's indent.
+    fn prefix(&self, marker: Option<&str>) -> String {
+        let innermost = self
+            .blocks
+            .iter()
+            .rposition(|block| matches!(block, Block::List { .. }));
+        let mut prefix = String::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            match block {
+                Block::Quote => prefix.push_str("> "),
+                Block::List { indent, .. } => match marker {
+                    Some(marker) if Some(index) == innermost => prefix.push_str(marker),
+                    _ => prefix.push_str(&" ".repeat(*indent)),
+                },
+            }
+        }
+        prefix
+    }
+
+    fn finish(mut self) -> String {
+        self.paragraph();
+        if !self.links.is_empty() {
+            self.lines.push(String::new());
+            for (index, link) in self.links.iter().enumerate() {
+                self.lines.push(format!("[{}] {}", index + 1, link));
+            }
+        }
+        let mut output = self.lines.join("\n").replace('\u{a0}', " ");
+        output.push('\n');
+        output
+    }
+}
+
+// Elements that start and end a paragraph
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "section"
+            | "article"
+            | "header"
+            | "footer"
+            | "nav"
+            | "aside"
+            | "main"
+            | "address"
+            | "center"
+            | "form"
+            | "fieldset"
+            | "figure"
+            | "figcaption"
+            | "dl"
+            | "dt"
+            | "dd"
+            | "table"
+            | "caption"
+    )
+}
+
+// Whitespace that collapses in HTML, which leaves out the no-break space
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c')
+}
+
+// Breaks text into lines of at most the width, except for words that are longer
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut length = 0;
+    for word in text.split(' ').filter(|word| !word.is_empty()) {
+        let word_length = word.chars().count();
+        if length > 0 && length + 1 + word_length > width {
+            lines.push(std::mem::take(&mut line));
+            length = 0;
+        }
+        if length > 0 {
+            line.push(' ');
+            length += 1;
+        }
+        line.push_str(word);
+        length += word_length;
+    }
+    lines.push(line);
+    lines
+}
+
+// Splits HTML into text and tags, with lowercased tag and attribute names. Comments,
+// doctypes and processing instructions are dropped, and so is everything inside a
+// script or style element, which may contain anything but its own end tag.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Token::Text(rest.to_string()));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+
+        let closing = rest.starts_with("</");
+        let name_start = if closing { 2 } else { 1 };
+        let name: String = rest[name_start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if name.is_empty() || !rest[name_start..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // A '<' that does not start a tag is text
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+            continue;
+        }
+
+        let (attributes, length) = read_attributes(&rest[name_start + name.len()..]);
+        rest = &rest[name_start + name.len() + length..];
+        if closing {
+            tokens.push(Token::End(name));
+            continue;
+        }
+
+        let raw_text = name == "script" || name == "style";
+        tokens.push(Token::Start(name.clone(), attributes));
+        if raw_text {
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&format!("</{}", name))
+                .unwrap_or(rest.len());
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+// Reads attributes up to the end of a tag, returning them with how many bytes were read
+fn read_attributes(tag: &str) -> (Vec<(String, String)>, usize) {
+    let mut attributes = Vec::new();
+    let mut chars = tag.char_indices().peekable();
+
+    while let Some(&(index, c)) = chars.peek() {
+        if c == '>' {
+            return (attributes, index + 1);
+        }
+        if c.is_whitespace() || c == '/' {
+            chars.next();
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(&(_, c)) = chars.peek() {
+            if c.is_whitespace() || matches!(c, '=' | '>' | '/') {
+                break;
+            }
+            name.push(c.to_ascii_lowercase());
+            chars.next();
+        }
+        if name.is_empty() {
+            chars.next();
+            continue;
+        }
+        while chars.peek().is_some_and(|&(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        if chars.peek().is_some_and(|&(_, c)| c == '=') {
+            chars.next();
+            while chars.peek().is_some_and(|&(_, c)| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.peek().map(|&(_, c)| c) {
+                Some(quote @ ('"' | '\'')) => {
+                    chars.next();
+                    for (_, c) in chars.by_ref() {
+                        if c == quote {
+                            break;
+                        }
+                        value.push(c);
+                    }
+                }
+                _ => {
+                    while let Some(&(_, c)) = chars.peek() {
+                        if c.is_whitespace() || c == '>' {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                }
+            }
+        }
+        attributes.push((name, decode_entities(&value)));
+    }
+    (attributes, tag.len())
+}
+
+// Decodes character references such as &amp;, &#233; and &#x1F600;. Unknown names are
+// left as written.
+pub fn decode_entities(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .take(32)
+            .find(|&(_, c)| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map_or(rest.len().min(33), |(index, _)| index);
+        let name = &rest[1..end];
+        let decoded = match name.strip_prefix('#') {
+            Some(number) => {
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                };
+                // NUL and numbers that are not characters are shown as U+FFFD, as
+                // browsers do
+                code.map(|code| {
+                    char::from_u32(code)
+                        .filter(|&c| c != '\0')
+                        .unwrap_or('\u{fffd}')
+                })
+            }
+            None => named_entity(name),
+        };
+        match decoded {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[end..];
+                rest = rest.strip_prefix(';').unwrap_or(rest);
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    let c = match name {
+        "amp" | "AMP" => '&',
+        "lt" | "LT" => '<',
+        "gt" | "GT" => '>',
+        "quot" | "QUOT" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "sbquo" => '‚',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        "middot" => '·',
+        "euro" => '€',
+        "pound" => '£',
+        "yen" => '¥',
+        "cent" => '¢',
+        "sect" => '§',
+        "para" => '¶',
+        "deg" => '°',
+        "plusmn" => '±',
+        "times" => '×',
+        "divide" => '÷',
+        "frac12" => '½',
+        "frac14" => '¼',
+        "frac34" => '¾',
+        "iexcl" => '¡',
+        "iquest" => '¿',
+        "shy" => '\u{ad}',
+        "zwnj" => '\u{200c}',
+        "zwj" => '\u{200d}',
+        "ensp" | "emsp" | "thinsp" => ' ',
+        "larr" => '←',
+        "rarr" => '→',
+        "uarr" => '↑',
+        "darr" => '↓',
+        "check" => '✓',
+        "szlig" => 'ß',
+        "aelig" => 'æ',
+        "AElig" => 'Æ',
+        "oslash" => 'ø',
+        "Oslash" => 'Ø',
+        "ccedil" => 'ç',
+        "Ccedil" => 'Ç',
+        "ntilde" => 'ñ',
+        "Ntilde" => 'Ñ',
+        _ => return accented(name),
+    };
+    Some(c)
+}
+
+// Letters with a diacritic, named as the letter followed by the mark, as in eacute
+fn accented(name: &str) -> Option<char> {
+    const MARKS: [(&str, &str, &str); 5] = [
+        ("grave", "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+        ("acute", "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+        ("circ", "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        ("uml", "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+        ("tilde", "aoAO", "ãõÃÕ"),
+    ];
+    let mut chars = name.chars();
+    let letter = chars.next()?;
+    let mark = chars.as_str();
+    let (_, letters, accented) = MARKS.iter().find(|(name, _, _)| *name == mark)?;
+    let index = letters.chars().position(|c| c == letter)?;
+    accented.chars().nth(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_hidden_elements_and_separates_paragraphs() {
+        let html = "<html><head><title>T</title><style>p{}</style></head><body>\
+<script>x()</script><p>One &amp; two</p><p>Three</p></body></html>";
+        assert_eq!(render(html, DEFAULT_WIDTH), "One & two\n\nThree\n");
+    }
+
+    #[test]
+    fn renders_lists() {
+        let html = "<ul><li>a<li>b</ul><ol start=\"3\"><li>c</li><li>d</li></ol>";
+        assert_eq!(render(html, DEFAULT_WIDTH), "* a\n* b\n\n3. c\n4. d\n");
+        let html = "<ul><li>a long item that wraps around nicely</li></ul>";
+        assert_eq!(
+            render(html, 20),
+            "* a long item that\n  wraps around nicely\n"
+        );
+    }
+
+    #[test]
+    fn stops_numbering_at_the_largest_start() {
+        let html = "<ol start=\"4294967295\"><li>a<li>b</ol>";
+        assert_eq!(
+            render(html, DEFAULT_WIDTH),
+            "4294967295. a\n4294967295. b\n"
+        );
+    }
+
+    #[test]
+    fn numbers_links_once_per_url() {
+        let html = "See <a href=\"https://example.com/\">the site</a> and \
+<a href=\"https://example.com/\">again</a>.";
+        assert_eq!(
+            render(html, DEFAULT_WIDTH),
+            "See the site [1] and again [1].\n\n[1] https://example.com/\n"
+        );
+    }
+
+    #[test]
+    fn marks_nested_quotes() {
+        let html = "<blockquote><p>quoted</p><blockquote>deeper</blockquote></blockquote>after";
+        assert_eq!(
+            render(html, DEFAULT_WIDTH),
+            "> quoted\n\n> > deeper\n\nafter\n"
+        );
+    }
+
+    #[test]
+    fn wraps_text_but_not_preformatted_text() {
+        let html = "<p>the quick brown fox jumps over the lazy dog</p>";
+        assert_eq!(
+            render(html, 20),
+            "the quick brown fox\njumps over the lazy\ndog\n"
+        );
+        assert_eq!(
+            render(html, 0),
+            "the quick brown fox jumps over the lazy dog\n"
+        );
+        assert_eq!(
+            render("<pre>  keep\n    this</pre>", 5),
+            "  keep\n    this\n"
+        );
+    }
+
+    #[test]
+    fn decodes_entities_and_keeps_unknown_ones() {
+        assert_eq!(
+            render("a<br>b &lt;c&gt; &#233;&#x20AC;&nbsp;&bogus; &amp", 0),
+            "a\nb <c> é€ &bogus; &\n"
+        );
+        assert_eq!(
+            decode_entities("&#0; &#xD800; &#99999999;"),
+            "\u{fffd} \u{fffd} \u{fffd}"
+        );
+    }
+
+    #[test]
+    fn survives_unclosed_and_stray_tags() {
+        assert_eq!(render("<p>unclosed <b>bold", 0), "unclosed bold\n");
+        assert_eq!(render("</li></ol></blockquote>text<", 0), "text<\n");
+        assert_eq!(render("", 0), "\n");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::append::Upload;
+use crate::checkpoint::Checkpoint;
+use crate::date::{asctime_to_internal_date, internal_date, valid_internal_date};
+use crate::email_error::EmailError;
+use crate::Server;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Limits on how much a single MULTIAPPEND command uploads
+const BATCH_MESSAGES: usize = 50;
+const BATCH_BYTES: usize = 8 * 1024 * 1024;
+
+pub struct ImportMessage {
+    key: String,
+    upload: Upload,
+}
+
+impl<'a> Server<'a> {
+    pub fn import(
+        &mut self,
+        folder: &str,
+        source: &str,
+        checkpoint_path: &str,
+    ) -> Result<String, EmailError> {
+        let path = Path::new(source);
+        let messages: Box<dyn Iterator<Item = Result<ImportMessage, EmailError>>> = if path.is_dir()
+        {
+            Box::new(maildir_entries(path)?.into_iter().map(read_maildir_message))
+        } else {
+            Box::new(MboxReader::open(path)?)
+        };
+
+        let checkpoint_path = if checkpoint_path.is_empty() {
+            format!("{}.checkpoint", source.trim_end_matches('/'))
+        } else {
+            checkpoint_path.to_string()
+        };
+        let mut checkpoint = Checkpoint::open(&checkpoint_path)?;
+
+        let batch_messages = if self.has_capability("MULTIAPPEND")? {
+            BATCH_MESSAGES
+        } else {
+            1
+        };
+
+        let mut batch: Vec<ImportMessage> = Vec::new();
+        let mut batch_bytes = 0;
+        let mut imported = 0;
+        let mut skipped = 0;
+
+        for message in messages {
+            let message = message?;
+            if checkpoint.contains(&message.key) {
+                skipped += 1;
+                continue;
+            }
+
+            batch_bytes += message.upload.message.len();
+            batch.push(message);
+
+            if batch.len() >= batch_messages || batch_bytes >= BATCH_BYTES {
+                imported += self.import_batch(folder, &mut batch, &mut checkpoint)?;
+                batch_bytes = 0;
+                eprint!("\rImported {} messages", imported);
+            }
+        }
+        if !batch.is_empty() {
+            imported += self.import_batch(folder, &mut batch, &mut checkpoint)?;
+            eprint!("\rImported {} messages", imported);
+        }
+        if imported > 0 {
+            eprintln!();
+        }
+
+        Ok(format!(
+            "Imported {} messages into {}, skipped {} already imported\n",
+            imported, folder, skipped
+        ))
+    }
+
+    // Uploads the batch and only then records it, so a failed batch is retried next run
+    fn import_batch(
+        &mut self,
+        folder: &str,
+        batch: &mut Vec<ImportMessage>,
+        checkpoint: &mut Checkpoint,
+    ) -> Result<usize, EmailError> {
+        let (keys, uploads): (Vec<String>, Vec<Upload>) = batch
+            .drain(..)
+            .map(|message| (message.key, message.upload))
+            .unzip();
+
+        self.append_messages(folder, &uploads)?;
+        checkpoint.record(&keys)?;
+        Ok(keys.len())
+    }
+}
+
+// Reads messages from an mbox file one at a time, undoing mboxrd From_ quoting
+pub struct MboxReader {
+    reader: BufReader<File>,
+    offset: u64,
+    next_from: Option<(u64, Vec<u8>)>,
+}
+
+impl MboxReader {
+    pub fn open(path: &Path) -> Result<MboxReader, EmailError> {
+        let file = File::open(path).map_err(|_| EmailError::FileNotFound)?;
+        let mut reader = MboxReader {
+            reader: BufReader::new(file),
+            offset: 0,
+            next_from: None,
+        };
+
+        // Skip anything before the first From_ line
+        while let Some(line) = reader.read_line()? {
+            if line.starts_with(b"From ") {
+                reader.next_from = Some((reader.offset - line.len() as u64, line));
+                break;
+            }
+        }
+        Ok(reader)
+    }
+
+    fn read_line(&mut self) -> Result<Option<Vec<u8>>, EmailError> {
+        let mut line = Vec::new();
+        let read = self
+            .reader
+            .read_until(b'\n', &mut line)
+            .map_err(|_| EmailError::FileNotFound)?;
+        self.offset += read as u64;
+        Ok(if read == 0 { None } else { Some(line) })
+    }
+}
+
+impl Iterator for MboxReader {
+    type Item = Result<ImportMessage, EmailError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (start, from_line) = self.next_from.take()?;
+        let mut message = Vec::new();
+        let mut previous_blank = false;
+
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // A From_ line after a blank line starts the next message
+            if previous_blank && line.starts_with(b"From ") {
+                self.next_from = Some((self.offset - line.len() as u64, line));
+                break;
+            }
+            previous_blank = line == b"\n" || line == b"\r\n";
+            message.extend_from_slice(&unquote_from(&line));
+        }
+
+        // The blank line before the separator belongs to the mbox, not the message
+        if message.ends_with(b"\r\n\r\n") {
+            message.truncate(message.len() - 2);
+        } else if message.ends_with(b"\n\n") {
+            message.truncate(message.len() - 1);
+        }
+
+        let from_line = String::from_utf8_lossy(&from_line).to_string();
+        let date = from_line
+            .trim_end()
+            .splitn(3, ' ')
+            .nth(2)
+            .and_then(asctime_to_internal_date)
+            // A malformed date would make the server reject the whole APPEND, so the
+            // message is appended without one instead
+            .filter(|date| valid_internal_date(date))
+            .unwrap_or_default();
+
+        Some(Ok(ImportMessage {
+            key: format!("mbox:{}", start),
+            upload: Upload {
+                message,
+                flags: String::new(),
+                date,
+            },
+        }))
+    }
+}
+
+// Strips one level of > from lines such as >From or >>From
+fn unquote_from(line: &[u8]) -> Vec<u8> {
+    let quotes = line.iter().take_while(|&&byte| byte == b'>').count();
+    if quotes > 0 && line[quotes..].starts_with(b"From ") {
+        line[1..].to_vec()
+    } else {
+        line.to_vec()
+    }
+}
+
+// Collects every message file in the cur and new directories of a Maildir tree
+fn maildir_entries(root: &Path) -> Result<Vec<PathBuf>, EmailError> {
+    let mut entries = Vec::new();
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        let name = directory.file_name().and_then(|name| name.to_str());
+        let is_mail = matches!(name, Some("cur") | Some("new"));
+
+        for entry in fs::read_dir(&directory).map_err(|_| EmailError::FileNotFound)? {
+            let path = entry.map_err(|_| EmailError::FileNotFound)?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.'));
+            if path.is_dir() {
+                if !is_mail && !path.ends_with("tmp") {
+                    directories.push(path);
+                }
+            } else if is_mail && !hidden {
+                entries.push(path);
+            }
+        }
+    }
+
+    entries.sort();
+    Ok(entries)
+}
+
+fn read_maildir_message(path: PathBuf) -> Result<ImportMessage, EmailError> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let (unique, info) = file_name.split_once(':').unwrap_or((file_name, ""));
+
+    let date = fs::metadata(&path)
+        .map_err(|_| EmailError::FileNotFound)?
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| internal_date(duration.as_secs() as i64))
+        .unwrap_or_default();
+
+    Ok(ImportMessage {
+        key: format!("maildir:{}", unique),
+        upload: Upload {
+            message: fs::read(&path).map_err(|_| EmailError::FileNotFound)?,
+            flags: maildir_flags(info),
+            date,
+        },
+    })
+}
+
+// Maps the flags after :2, in a Maildir file name to IMAP system flags
+pub fn maildir_flags(info: &str) -> String {
+    let Some(letters) = info.strip_prefix("2,") else {
+        return String::new();
+    };
+
+    letters
+        .chars()
+        .filter_map(|letter| match letter {
+            'S' => Some("\\Seen"),
+            'R' => Some("\\Answered"),
+            'F' => Some("\\Flagged"),
+            'T' => Some("\\Deleted"),
+            'D' => Some("\\Draft"),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
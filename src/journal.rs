@@ -0,0 +1,482 @@
+use crate::cache::{path_component, root_directory};
+use crate::date::internal_date;
+use crate::email_error::EmailError;
+use crate::helper::{response_code, to_sequence_set};
+use crate::Server;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How many messages are saved per FETCH before an expunge
+const BATCH_MESSAGES: usize = 50;
+
+// Records what each mutating command did to the server, so undo can reverse it. Every
+// account has a journal under <root>/journal/<account>/, where the operations file
+// holds tab-separated lines:
+//
+//   operation  <id>  <date>  <command>
+//   move       <folder>  <uidvalidity>  <destination>  <uidvalidity>  <uid>|<new uid> ...
+//   copy       <folder>  <uidvalidity>  <destination>  <uidvalidity>  <uid>|<new uid> ...
+//   flags      <folder>  <uidvalidity>  <uid>|<flags before> ...
+//   expunge    <folder>  <uidvalidity>  <uid>|<file>|<internal date>|<flags> ...
+//   undone     <id>
+//   renamed    <folder>  <uid>|<new uid> ...
+//
+// Expunged messages are saved to messages/ first, since nothing else could bring them back.
+pub struct Journal {
+    directory: PathBuf,
+    command: String,
+    // Assigned when the command first changes something
+    operation: Option<u32>,
+    saved: u32,
+}
+
+impl Journal {
+    pub fn new(root: &str, account: &str, command: &str) -> Journal {
+        Journal {
+            directory: journal_directory(root, account),
+            command: command.to_string(),
+            operation: None,
+            saved: 0,
+        }
+    }
+
+    fn start(&mut self) -> Result<u32, EmailError> {
+        if let Some(operation) = self.operation {
+            return Ok(operation);
+        }
+        fs::create_dir_all(self.directory.join("messages"))
+            .map_err(|_| EmailError::JournalFailure)?;
+        let operation = read_operations(&self.directory)?
+            .0
+            .last()
+            .map_or(1, |last| last.id + 1);
+        self.operation = Some(operation);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        self.append(&[
+            "operation".to_string(),
+            operation.to_string(),
+            internal_date(now),
+            self.command.clone(),
+        ])?;
+        Ok(operation)
+    }
+
+    fn record(&mut self, fields: &[String]) -> Result<(), EmailError> {
+        self.start()?;
+        self.append(fields)
+    }
+
+    fn append(&self, fields: &[String]) -> Result<(), EmailError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join("operations"))
+            .map_err(|_| EmailError::JournalFailure)?;
+        writeln!(file, "{}", fields.join("\t")).map_err(|_| EmailError::JournalFailure)
+    }
+
+    // Returns the file name the message was saved under
+    fn save_message(&mut self, message: &[u8]) -> Result<String, EmailError> {
+        let operation = self.start()?;
+        self.saved += 1;
+        let name = format!("{}-{}.eml", operation, self.saved);
+        fs::write(self.directory.join("messages").join(&name), message)
+            .map_err(|_| EmailError::JournalFailure)?;
+        Ok(name)
+    }
+}
+
+fn journal_directory(root: &str, account: &str) -> PathBuf {
+    root_directory(root)
+        .join("journal")
+        .join(path_component(account))
+}
+
+// One command's worth of journal lines
+struct Operation {
+    id: u32,
+    date: String,
+    command: String,
+    records: Vec<Vec<String>>,
+    undone: bool,
+}
+
+// The UIDs undo gave messages it moved back or restored, so undoing an older operation
+// still finds them
+#[derive(Default)]
+struct Renames {
+    uids: HashMap<(String, u32), u32>,
+    added: BTreeMap<String, Vec<(u32, u32)>>,
+}
+
+impl Renames {
+    // UIDs only ever grow, so following the chain always ends
+    fn resolve(&self, folder: &str, uid: u32) -> u32 {
+        let mut uid = uid;
+        while let Some(&next) = self.uids.get(&(folder.to_string(), uid)) {
+            uid = next;
+        }
+        uid
+    }
+
+    fn insert(&mut self, folder: &str, old: u32, new: u32) {
+        self.uids.insert((folder.to_string(), old), new);
+        self.added
+            .entry(folder.to_string())
+            .or_default()
+            .push((old, new));
+    }
+}
+
+fn read_operations(directory: &Path) -> Result<(Vec<Operation>, Renames), EmailError> {
+    let contents = match fs::read_to_string(directory.join("operations")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(_) => return Err(EmailError::JournalFailure),
+    };
+
+    let mut operations: Vec<Operation> = Vec::new();
+    let mut renames = Renames::default();
+    for line in contents.lines() {
+        let fields: Vec<String> = line.split('\t').map(|field| field.to_string()).collect();
+        match fields[0].as_str() {
+            "operation" if fields.len() == 4 => operations.push(Operation {
+                id: fields[1].parse().map_err(|_| EmailError::JournalFailure)?,
+                date: fields[2].clone(),
+                command: fields[3].clone(),
+                records: Vec::new(),
+                undone: false,
+            }),
+            "undone" if fields.len() == 2 => {
+                let id: u32 = fields[1].parse().map_err(|_| EmailError::JournalFailure)?;
+                if let Some(operation) = operations.iter_mut().find(|operation| operation.id == id)
+                {
+                    operation.undone = true;
+                }
+            }
+            "renamed" if fields.len() >= 2 => {
+                for pair in &fields[2..] {
+                    let (old, new) = pair.split_once('|').ok_or(EmailError::JournalFailure)?;
+                    let (Ok(old), Ok(new)) = (old.parse(), new.parse()) else {
+                        return Err(EmailError::JournalFailure);
+                    };
+                    renames.uids.insert((fields[1].clone(), old), new);
+                }
+            }
+            _ => match operations.last_mut() {
+                Some(operation) if fields.len() >= 3 => operation.records.push(fields),
+                _ => return Err(EmailError::JournalFailure),
+            },
+        }
+    }
+    Ok((operations, renames))
+}
+
+// Lists the journaled operations, oldest first
+pub fn list_journal(root: &str, account: &str) -> Result<String, EmailError> {
+    let (operations, _) = read_operations(&journal_directory(root, account))?;
+    let mut output = String::new();
+    for operation in &operations {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for record in &operation.records {
+            let messages = match record[0].as_str() {
+                "move" | "copy" => record.len().saturating_sub(5),
+                _ => record.len() - 3,
+            };
+            *counts.entry(record[0].as_str()).or_default() += messages;
+        }
+        let summary: Vec<String> = counts
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        output.push_str(&format!(
+            "{} {} {}: {}{}\n",
+            operation.id,
+            operation.date,
+            operation.command,
+            summary.join(", "),
+            if operation.undone { " (undone)" } else { "" }
+        ));
+    }
+    Ok(output)
+}
+
+impl<'a> Server<'a> {
+    pub fn start_journal(&mut self, root: &str, account: &str, command: &str) {
+        self.journal = Some(Journal::new(root, account, command));
+    }
+
+    // Records a COPY or MOVE from the selected folder, with the (old, new) UID pairs
+    // COPYUID gave to know where the messages went
+    pub fn record_transfer(
+        &mut self,
+        kind: &str,
+        destination: &str,
+        response: &str,
+        mapping: &[(u32, u32)],
+    ) -> Result<(), EmailError> {
+        let Some(journal) = self.journal.as_mut() else {
+            return Ok(());
+        };
+        let code = response_code(response, "COPYUID").unwrap_or_default();
+        let parts: Vec<&str> = code.split_whitespace().collect();
+        let mut fields = vec![
+            kind.to_string(),
+            self.mailbox.name.clone(),
+            self.mailbox.uid_validity.to_string(),
+            destination.to_string(),
+            parts.first().unwrap_or(&"0").to_string(),
+        ];
+        fields.extend(mapping.iter().map(|(old, new)| format!("{}|{}", old, new)));
+        journal.record(&fields)
+    }
+
+    // Reads the flags a STORE is about to change, when journaling
+    pub fn flags_before_store(&mut self, uid_set: &str) -> Result<Option<Vec<String>>, EmailError> {
+        if self.journal.is_none() {
+            return Ok(None);
+        }
+        let previous = self
+            .uid_fetch(uid_set, "UID FLAGS")?
+            .iter()
+            .map(|fetch| format!("{}|{}", fetch.uid(), fetch.flags().join(" ")))
+            .collect();
+        Ok(Some(previous))
+    }
+
+    pub fn record_flags(&mut self, previous: Vec<String>) -> Result<(), EmailError> {
+        let mut fields = vec![
+            "flags".to_string(),
+            self.mailbox.name.clone(),
+            self.mailbox.uid_validity.to_string(),
+        ];
+        fields.extend(previous);
+        match self.journal.as_mut() {
+            Some(journal) if fields.len() > 3 => journal.record(&fields),
+            _ => Ok(()),
+        }
+    }
+
+    // Saves the messages an expunge is about to remove, when journaling
+    pub fn save_before_expunge(
+        &mut self,
+        uid_set: &str,
+    ) -> Result<Option<Vec<String>>, EmailError> {
+        if self.journal.is_none() {
+            return Ok(None);
+        }
+        let uids = self.uid_search(&format!("UID {} DELETED", uid_set))?;
+
+        let mut saved = Vec::new();
+        for batch in uids.chunks(BATCH_MESSAGES) {
+            let fetches = self.uid_fetch(
+                &to_sequence_set(batch),
+                "UID FLAGS INTERNALDATE BODY.PEEK[]",
+            )?;
+            let Some(journal) = self.journal.as_mut() else {
+                break;
+            };
+            // Every message must be saved before the expunge, or undo could not restore it
+            for &uid in batch {
+                let Some((fetch, message)) = fetches
+                    .iter()
+                    .filter(|fetch| fetch.uid() == uid)
+                    .find_map(|fetch| fetch.get("BODY[]").map(|message| (fetch, message)))
+                else {
+                    eprintln!("Could not save message {} to the journal", uid);
+                    return Err(EmailError::MessageNotFound);
+                };
+                let file = journal.save_message(message)?;
+                saved.push(format!(
+                    "{}|{}|{}|{}",
+                    fetch.uid(),
+                    file,
+                    fetch.text("INTERNALDATE"),
+                    fetch.flags().join(" ")
+                ));
+            }
+        }
+        Ok(Some(saved))
+    }
+
+    pub fn record_expunge(&mut self, saved: Vec<String>) -> Result<(), EmailError> {
+        let mut fields = vec![
+            "expunge".to_string(),
+            self.mailbox.name.clone(),
+            self.mailbox.uid_validity.to_string(),
+        ];
+        fields.extend(saved);
+        match self.journal.as_mut() {
+            Some(journal) if fields.len() > 3 => journal.record(&fields),
+            _ => Ok(()),
+        }
+    }
+
+    // Reverses the chosen operation, or the latest one not yet undone. Records are
+    // undone newest first, so a message moved back or restored gets a new UID that the
+    // earlier records are then translated to.
+    pub fn undo(
+        &mut self,
+        root: &str,
+        account: &str,
+        chosen: Option<u32>,
+    ) -> Result<String, EmailError> {
+        let directory = journal_directory(root, account);
+        let (operations, mut renamed) = read_operations(&directory)?;
+        let operation = match chosen {
+            Some(id) => operations.iter().find(|operation| operation.id == id),
+            None => operations.iter().rev().find(|operation| !operation.undone),
+        }
+        .filter(|operation| !operation.undone)
+        .ok_or(EmailError::OperationNotFound)?;
+
+        // Refuse before changing anything if a folder has been recreated since. A
+        // destination without COPYUID has no UIDVALIDITY to compare.
+        let mut validities: BTreeMap<&str, &str> = BTreeMap::new();
+        for record in &operation.records {
+            validities.insert(&record[1], &record[2]);
+            if matches!(record[0].as_str(), "move" | "copy") && record.len() > 5 {
+                validities.insert(&record[3], &record[4]);
+            }
+        }
+        for (folder, validity) in &validities {
+            self.select(folder)?;
+            if self.mailbox.uid_validity.to_string() != *validity {
+                return Err(EmailError::UndoFailure);
+            }
+        }
+
+        let mut output = String::new();
+        for record in operation.records.iter().rev() {
+            let folder = &record[1];
+            let name = folder.trim_matches('"');
+            match record[0].as_str() {
+                kind @ ("move" | "copy") if record.len() >= 5 => {
+                    let destination = &record[3];
+                    let pairs: Vec<(u32, u32)> = record[5..]
+                        .iter()
+                        .filter_map(|pair| {
+                            let (old, new) = pair.split_once('|')?;
+                            let new: u32 = new.parse().ok()?;
+                            Some((old.parse().ok()?, renamed.resolve(destination, new)))
+                        })
+                        .collect();
+                    if pairs.is_empty() {
+                        eprintln!(
+                            "Cannot find the messages in {} without COPYUID",
+                            destination.trim_matches('"')
+                        );
+                        continue;
+                    }
+                    let targets: Vec<u32> = pairs.iter().map(|(_, new)| *new).collect();
+                    let uid_set = to_sequence_set(&targets);
+                    self.select(destination)?;
+
+                    if kind == "copy" {
+                        self.uid_store(&uid_set, "+FLAGS.SILENT (\\Deleted)")?;
+                        self.uid_expunge(&uid_set)?;
+                        output.push_str(&format!(
+                            "Removed {} copies from {}\n",
+                            targets.len(),
+                            destination.trim_matches('"')
+                        ));
+                        continue;
+                    }
+
+                    for (from, back) in self.uid_move(&uid_set, folder)? {
+                        if let Some((old, _)) = pairs.iter().find(|(_, new)| *new == from) {
+                            renamed.insert(folder, *old, back);
+                        }
+                    }
+                    output.push_str(&format!(
+                        "Moved {} messages from {} back to {}\n",
+                        targets.len(),
+                        destination.trim_matches('"'),
+                        name
+                    ));
+                }
+                "flags" => {
+                    self.select(folder)?;
+                    let mut by_flags: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+                    for entry in &record[3..] {
+                        let Some((uid, flags)) = entry.split_once('|') else {
+                            continue;
+                        };
+                        let Ok(uid) = uid.parse::<u32>() else {
+                            continue;
+                        };
+                        let uid = renamed.resolve(folder, uid);
+                        by_flags.entry(storable(flags)).or_default().push(uid);
+                    }
+                    let mut restored = 0;
+                    for (flags, uids) in &by_flags {
+                        let flags = format!("FLAGS.SILENT ({})", flags);
+                        self.uid_store(&to_sequence_set(uids), &flags)?;
+                        restored += uids.len();
+                    }
+                    output.push_str(&format!(
+                        "Restored flags on {} messages in {}\n",
+                        restored, name
+                    ));
+                }
+                "expunge" => {
+                    let mut restored = 0;
+                    for entry in &record[3..] {
+                        let parts: Vec<&str> = entry.splitn(4, '|').collect();
+                        let [uid, file, date, flags] = parts[..] else {
+                            continue;
+                        };
+                        let message = fs::read(directory.join("messages").join(file))
+                            .map_err(|_| EmailError::JournalFailure)?;
+                        let flags: Vec<&str> = flags
+                            .split_whitespace()
+                            .filter(|flag| !flag.eq_ignore_ascii_case("\\Deleted"))
+                            .collect();
+                        let response = self.append_message(
+                            folder,
+                            &message,
+                            &storable(&flags.join(" ")),
+                            date,
+                        )?;
+                        // APPENDUID carries the UIDVALIDITY and the new UID
+                        let new_uid = response_code(&response, "APPENDUID")
+                            .and_then(|code| code.split_whitespace().nth(1)?.parse().ok());
+                        if let (Ok(uid), Some(new_uid)) = (uid.parse(), new_uid) {
+                            renamed.insert(folder, uid, new_uid);
+                        }
+                        restored += 1;
+                    }
+                    output.push_str(&format!(
+                        "Restored {} expunged messages to {}\n",
+                        restored, name
+                    ));
+                }
+                _ => return Err(EmailError::JournalFailure),
+            }
+        }
+
+        let journal = Journal::new(root, account, "undo");
+        for (folder, pairs) in &renamed.added {
+            let mut fields = vec!["renamed".to_string(), folder.clone()];
+            fields.extend(pairs.iter().map(|(old, new)| format!("{}|{}", old, new)));
+            journal.append(&fields)?;
+        }
+        journal.append(&["undone".to_string(), operation.id.to_string()])?;
+        output.push_str(&format!("Undid operation {}\n", operation.id));
+        Ok(output)
+    }
+}
+
+// \Recent is set by the server alone, so it cannot be stored back
+fn storable(flags: &str) -> String {
+    flags
+        .split_whitespace()
+        .filter(|flag| !flag.eq_ignore_ascii_case("\\Recent"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,10 +1,11 @@
 use crate::email_error::EmailError;
+use crate::header::decode_words;
 use crate::Server;
 
 impl<'a> Server<'a> {
-    pub fn list(&mut self) -> Result<String, EmailError> {
+    pub fn list(&mut self, raw: bool) -> Result<String, EmailError> {
         let command = "FETCH 1:* BODY.PEEK[HEADER.FIELDS (SUBJECT)]";
-        let response = self.run_command(&command)?;
+        let response = self.run_command(command)?;
         let lines = response.split("\r\n").collect::<Vec<&str>>();
         let lines_len = lines.len();
         let mut response = String::new();
@@ -14,29 +15,37 @@ impl<'a> Server<'a> {
             let line = lines[i];
             i += 1;
 
-            let number = line.as_bytes()[2] as char;
+            // * <number> FETCH ...
+            let number = line[2..].split(' ').next().unwrap_or_default();
 
             let subject_line = get_subject_line(&lines, &mut i);
 
-            let subject: &str;
-
-            if subject_line.is_empty() {
-                subject = "<No subject>";
-            } else {
-                subject = &subject_line[9..subject_line.len()];
-            }
-
-            response.push(number);
-            response.push_str(": ");
-            response.push_str(subject);
-            response.push('\n');
+            response.push_str(&list_entry(number, &subject_line, raw));
         }
 
         Ok(response)
     }
 }
 
-fn get_subject_line(lines: &Vec<&str>, i: &mut usize) -> String {
+// The subject line is the raw header with its folded lines joined back together.
+// Encoded words in it are decoded unless the raw form was asked for.
+pub fn list_entry(number: &str, subject_line: &str, raw: bool) -> String {
+    // The value follows the colon and the space after it, if there is one
+    let value = subject_line
+        .split_once(':')
+        .map_or("", |(_, value)| value.strip_prefix(' ').unwrap_or(value));
+    let subject = if subject_line.is_empty() {
+        "<No subject>".to_string()
+    } else if raw {
+        value.to_string()
+    } else {
+        decode_words(value)
+    };
+
+    format!("{}: {}\n", number, subject)
+}
+
+fn get_subject_line(lines: &[&str], i: &mut usize) -> String {
     let mut subject = String::new();
     let mut terminating = false;
 
@@ -44,7 +53,7 @@ fn get_subject_line(lines: &Vec<&str>, i: &mut usize) -> String {
         let line = lines[*i];
         *i += 1;
 
-        if line == "" {
+        if line.is_empty() {
             terminating = true;
             continue;
         }
@@ -1,19 +1,54 @@
 use std::env;
 
+mod address;
+mod append;
+mod archive;
 mod arguments;
+mod attachments;
+mod cache;
+mod charset;
+mod checkpoint;
+mod config;
+mod copy;
+mod daemon;
+mod date;
+mod dedupe;
+mod deliver;
 mod email_error;
+mod encoding;
+mod export;
 mod fetch;
 mod file;
+mod filter;
+mod header;
 mod helper;
+mod html;
+mod import;
+mod journal;
 mod list;
 mod mime;
+mod offline;
 mod parse;
+mod part;
+mod response;
+mod rules;
+mod search;
 mod server;
-use crate::arguments::print_args;
-use crate::email_error::handle_error;
+mod structure;
+mod sync;
+mod watch;
+use crate::archive::Policy;
+use crate::arguments::{print_args, Args};
+use crate::daemon::run_daemon;
+use crate::deliver::Delivery;
+use crate::email_error::{handle_error, EmailError};
+use crate::html::DEFAULT_WIDTH;
+use crate::journal::list_journal;
+use crate::mime::MimeOptions;
+use crate::offline::Offline;
+use crate::parse::HeaderFormat;
+use crate::rules::read_rules;
 use crate::server::{Server, Streamable};
-use openssl::ssl::SslStream;
-use std::net::TcpStream;
 
 fn main() {
     let debug: bool = false;
@@ -27,51 +62,214 @@ fn main() {
         handle_error(err)
     }
 
-    let mut stream: Option<Box<dyn Streamable>> = None;
+    if parsed_args.offline {
+        run_offline(&parsed_args);
+        return;
+    }
 
-    if parsed_args.use_tsl {
-        let ssl_stream = server::create_ssl_stream(&parsed_args.server_name);
+    if parsed_args.command == "daemon" {
+        let response = run_daemon(
+            &parsed_args.config,
+            &parsed_args.cache,
+            &parsed_args.log,
+            parsed_args.interval,
+        );
+        print_response(&parsed_args.command, response);
+        return;
+    }
 
-        match ssl_stream {
-            Ok(stream_result) => {
-                stream = Some(Box::<SslStream<TcpStream>>::new(stream_result));
-            }
-            Err(e) => handle_error(e),
-        }
-    } else {
-        let tcp_stream = server::create_tcp_stream(&parsed_args.server_name);
+    let account = format!("{}@{}", parsed_args.username, parsed_args.server_name);
+    if parsed_args.command == "journal" {
+        let response = list_journal(&parsed_args.cache, &account);
+        print_response(&parsed_args.command, response);
+        return;
+    }
 
-        match tcp_stream {
-            Ok(stream_result) => {
-                stream = Some(Box::<TcpStream>::new(stream_result));
-            }
-            Err(e) => handle_error(e),
-        }
+    // Read before connecting, so a broken rules file fails fast
+    let rules = match parsed_args.rules.as_str() {
+        "" => Vec::new(),
+        path => read_rules(path).unwrap_or_else(|e| {
+            handle_error(e);
+            Vec::new()
+        }),
+    };
+
+    let mut stream: Option<Box<dyn Streamable>> = None;
+
+    match server::create_stream(&parsed_args.server_name, parsed_args.use_tsl) {
+        Ok(stream_result) => stream = Some(stream_result),
+        Err(e) => handle_error(e),
     }
 
     let mut server = Server::new(&parsed_args.username, &parsed_args.password, debug);
+    server.create_folders = parsed_args.create;
+
+    // Appending targets a folder without needing it selected, and sync, dedupe and undo
+    // select their own
+    let needs_select = !matches!(
+        parsed_args.command.as_str(),
+        "append" | "import" | "sync" | "dedupe" | "undo"
+    );
 
     let res = server
         .connect(stream.unwrap())
         .and_then(|_| server.login())
-        .and_then(|_| server.select(&parsed_args.folder));
+        .and_then(|_| {
+            if needs_select {
+                server.select(&parsed_args.folder)
+            } else {
+                Ok(String::new())
+            }
+        });
 
     match res {
         Ok(_) => (),
         Err(e) => handle_error(e),
     }
 
+    // Anything that can change messages on the server is journaled so it can be undone
+    let mutating = matches!(
+        parsed_args.command.as_str(),
+        "copy" | "move" | "sync" | "filter" | "deliver" | "watch" | "archive" | "dedupe"
+    );
+    if mutating && !parsed_args.dry_run {
+        server.start_journal(&parsed_args.cache, &account, &parsed_args.command);
+    }
+
     let response = match parsed_args.command.as_str() {
         "retrieve" => server.fetch(parsed_args.message_number),
-        "parse" => server.parse(parsed_args.message_number),
-        "mime" => server.mime(parsed_args.message_number),
-        "list" => server.list(),
+        "parse" => server.parse(parsed_args.message_number, &header_format(&parsed_args)),
+        "mime" => server.mime(parsed_args.message_number, &mime_options(&parsed_args)),
+        "structure" => server.structure(parsed_args.message_number),
+        "attachments" => server.attachments(
+            parsed_args.message_number,
+            &parsed_args.output,
+            &parsed_args.part,
+        ),
+        "list" => server.list(parsed_args.raw),
+        "copy" => server.copy(parsed_args.message_number, &parsed_args.destination),
+        "move" => server.move_message(parsed_args.message_number, &parsed_args.destination),
+        "append" => server.append(
+            &parsed_args.folder,
+            &parsed_args.file,
+            &parsed_args.flags,
+            &parsed_args.date,
+        ),
+        "import" => server.import(
+            &parsed_args.folder,
+            &parsed_args.file,
+            &parsed_args.checkpoint,
+        ),
+        "search" => server.search(&parsed_args.search),
+        "export" => server.export(
+            &parsed_args.search,
+            &parsed_args.format,
+            &parsed_args.output,
+            &parsed_args.template,
+            &parsed_args.checkpoint,
+        ),
+        "sync" => server.sync(
+            &parsed_args.folder,
+            &parsed_args.cache,
+            &account,
+            &rules,
+            parsed_args.dry_run,
+        ),
+        "filter" => server.filter(&rules, &parsed_args.search, parsed_args.dry_run),
+        "deliver" => match Delivery::new(&parsed_args.mda, &parsed_args.maildir) {
+            Some(delivery) => server.deliver(&parsed_args.search, &delivery, &parsed_args.after),
+            None => Err(EmailError::InvalidArguments),
+        },
+        "watch" => server.watch(
+            parsed_args.interval,
+            &parsed_args.exec,
+            &rules,
+            parsed_args.dry_run,
+        ),
+        "archive" => server.archive(
+            &parsed_args.folder,
+            &Policy {
+                days: parsed_args.days,
+                purge_days: parsed_args.purge_days,
+                limit: parsed_args.limit,
+                date_source: &parsed_args.date_source,
+                template: &parsed_args.template,
+                dry_run: parsed_args.dry_run,
+            },
+        ),
+        "dedupe" => {
+            let folders = if parsed_args.folders.is_empty() {
+                vec![parsed_args.folder.clone()]
+            } else {
+                parsed_args.folders.clone()
+            };
+            server.dedupe(
+                &folders,
+                &parsed_args.search,
+                &parsed_args.destination,
+                parsed_args.delete,
+                parsed_args.dry_run,
+            )
+        }
+        "undo" => server.undo(&parsed_args.cache, &account, parsed_args.operation),
         _ => Ok("".to_string()),
     };
 
-    let newline = match parsed_args.command.as_str() {
+    print_response(&parsed_args.command, response);
+
+    server.shutdown();
+}
+
+// Answers from the folder's local cache, which sync fills, without connecting
+fn run_offline(parsed_args: &Args) {
+    let account = format!("{}@{}", parsed_args.username, parsed_args.server_name);
+    let response =
+        Offline::open(&parsed_args.cache, &account, &parsed_args.folder).and_then(|offline| {
+            match parsed_args.command.as_str() {
+                "retrieve" => offline.fetch(parsed_args.message_number),
+                "parse" => offline.parse(parsed_args.message_number, &header_format(parsed_args)),
+                "mime" => offline.mime(parsed_args.message_number, &mime_options(parsed_args)),
+                "structure" => offline.structure(parsed_args.message_number),
+                "attachments" => offline.attachments(
+                    parsed_args.message_number,
+                    &parsed_args.output,
+                    &parsed_args.part,
+                ),
+                "list" => offline.list(parsed_args.raw),
+                "search" => offline.search(&parsed_args.search),
+                _ => Err(EmailError::OfflineUnsupported),
+            }
+        });
+
+    print_response(&parsed_args.command, response);
+}
+
+fn header_format(parsed_args: &Args) -> HeaderFormat<'_> {
+    HeaderFormat {
+        fields: &parsed_args.headers,
+        all_fields: parsed_args.all_headers,
+        raw: parsed_args.raw,
+        normalize: parsed_args.normalize,
+        date_format: &parsed_args.date_format,
+    }
+}
+
+fn mime_options(parsed_args: &Args) -> MimeOptions<'_> {
+    MimeOptions {
+        raw: parsed_args.raw,
+        prefer: &parsed_args.prefer,
+        part: &parsed_args.part,
+        render: parsed_args.render,
+        width: parsed_args.width.unwrap_or(DEFAULT_WIDTH),
+    }
+}
+
+fn print_response(command: &str, response: Result<String, EmailError>) {
+    let newline = match command {
         "retrieve" => "\r\n",
-        "parse" | "mime" | "list" => "",
+        "parse" | "mime" | "list" | "copy" | "move" | "append" | "import" | "search" | "export"
+        | "sync" | "watch" | "daemon" | "deliver" | "filter" | "archive" | "dedupe" | "undo"
+        | "journal" | "structure" | "attachments" => "",
         _ => "",
     };
 
@@ -79,6 +277,4 @@ fn main() {
         Ok(result) => print!("{}{}", result, newline),
         Err(e) => handle_error(e),
     };
-
-    server.shutdown();
 }
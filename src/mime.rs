@@ -1,34 +1,162 @@
+use crate::charset::to_utf8;
 use crate::email_error::EmailError;
+use crate::encoding::decode;
+use crate::html::render;
+use crate::part::{parse_parameters, Part};
 use crate::Server;
 
 impl<'a> Server<'a> {
-    pub fn mime(&mut self, message_num: u32) -> Result<String, EmailError> {
+    pub fn mime(&mut self, message_num: u32, options: &MimeOptions) -> Result<String, EmailError> {
+        if !options.raw {
+            return mime_text(&self.fetch_bytes(message_num)?, options);
+        }
+
         // check mime version
-        let response = self.fetch_header(message_num, "MIME-VERSION")?;
+        let mime_version = self.fetch_header(message_num, "MIME-VERSION")?;
+        check_mime_version(&mime_version)?;
+
+        let content_type = self.fetch_header(message_num, "CONTENT-TYPE")?;
+        let body = self.fetch(message_num)?;
+        mime_from_body(content_type, body)
+    }
+}
+
+// Which part mime shows. A part number such as 1.2 picks one directly, and otherwise
+// the body text is found, preferring text/plain unless text/html is asked for. HTML
+// found in place of text/plain is rendered as text wrapped to the width, as is any
+// HTML when rendering is asked for. Raw keeps the original behaviour of showing the
+// text/plain part of a multipart/alternative message as it was sent.
+pub struct MimeOptions<'m> {
+    pub raw: bool,
+    pub prefer: &'m str,
+    pub part: &'m str,
+    pub render: bool,
+    pub width: usize,
+}
+
+pub const PREFER_TYPES: [&str; 2] = ["text/plain", "text/html"];
+
+// Finds the part to show, undoes its transfer encoding and converts it to UTF-8
+pub fn mime_text(message: &[u8], options: &MimeOptions) -> Result<String, EmailError> {
+    let root = Part::parse(message);
+    match root
+        .header
+        .get("MIME-Version")
+        .map(|version| parse_parameters(version).0)
+    {
+        None => return Err(EmailError::MimeMissing),
+        Some(version) if version != "1.0" => return Err(EmailError::InvalidMimeVersion),
+        Some(_) => (),
+    }
+    if root.content_type.is_multipart() && root.content_type.parameter("boundary").is_none() {
+        return Err(EmailError::BoundaryParameterMissing);
+    }
 
-        if response.is_empty() {
-            return Err(EmailError::MimeMissing);
-        } else if response.to_lowercase() != "mime-version: 1.0" {
-            return Err(EmailError::InvalidMimeVersion);
+    let part = if options.part.is_empty() {
+        let prefer = if options.prefer.is_empty() {
+            "text/plain"
+        } else {
+            options.prefer
+        };
+        body_text(&root, prefer).ok_or(EmailError::NoTextPart)?
+    } else {
+        let part = root.find(options.part).ok_or(EmailError::PartNotFound)?;
+        if !part.content_type.mime_type.starts_with("text/") {
+            return Err(EmailError::NoTextPart);
         }
+        part
+    };
+
+    let body = decode(
+        &message[part.body.clone()],
+        part.header.get("Content-Transfer-Encoding"),
+    );
+    let converted = to_utf8(&body, part.content_type.parameter("charset"));
+    if converted.replaced > 0 {
+        eprintln!(
+            "Replaced {} bytes that could not be decoded as {}",
+            converted.replaced, converted.charset
+        );
+    }
 
-        let response = self.fetch_header(message_num, "CONTENT-TYPE")?;
-        let boundary_parameter = parse_content_type(response)?;
+    let fallback = options.part.is_empty() && options.prefer != "text/html";
+    if part.content_type.mime_type == "text/html" && (options.render || fallback) {
+        return Ok(render(&converted.text, options.width));
+    }
+    Ok(converted.text)
+}
 
-        let body = self.fetch(message_num)?;
-        let response = parse_mime_from_body(body, boundary_parameter);
+// The part holding a message's text. Of alternatives the preferred type wins, and
+// failing that the first that has any text. In other multiparts, such as mixed or
+// related, the body comes first and attachments after, so the first part that is not
+// an attachment and has text is taken. An attached message is looked into as well.
+fn body_text<'p>(part: &'p Part, prefer: &str) -> Option<&'p Part> {
+    let content_type = &part.content_type;
+    if content_type.mime_type == "multipart/alternative" {
+        let texts: Vec<&Part> = part
+            .children
+            .iter()
+            .filter_map(|child| body_text(child, prefer))
+            .collect();
+        texts
+            .iter()
+            .find(|text| text.content_type.mime_type == prefer)
+            .or(texts.first())
+            .copied()
+    } else if content_type.is_multipart() {
+        part.children
+            .iter()
+            .filter(|child| !is_attachment(child))
+            .find_map(|child| body_text(child, prefer))
+    } else if content_type.is_message() {
+        part.children
+            .first()
+            .and_then(|message| body_text(message, prefer))
+    } else if PREFER_TYPES.contains(&content_type.mime_type.as_str()) {
+        Some(part)
+    } else {
+        None
+    }
+}
 
-        // do the slice to remove the trailing newline
-        Ok(String::from(&response[..response.len() - 2]))
+fn is_attachment(part: &Part) -> bool {
+    part.header
+        .get("Content-Disposition")
+        .is_some_and(|disposition| {
+            parse_parameters(disposition)
+                .0
+                .eq_ignore_ascii_case("attachment")
+        })
+}
+
+pub fn check_mime_version(mime_version: &str) -> Result<(), EmailError> {
+    if mime_version.is_empty() {
+        Err(EmailError::MimeMissing)
+    } else if mime_version.to_lowercase() != "mime-version: 1.0" {
+        Err(EmailError::InvalidMimeVersion)
+    } else {
+        Ok(())
     }
 }
 
+// Extracts the text/plain part given the unwrapped Content-Type header and the retrieved body
+pub fn mime_from_body(content_type: String, body: String) -> Result<String, EmailError> {
+    let boundary_parameter = parse_content_type(content_type)?;
+    let response = parse_mime_from_body(body, boundary_parameter);
+
+    // do the slice to remove the trailing newline
+    Ok(String::from(&response[..response.len() - 2]))
+}
+
 fn parse_content_type(content_header: String) -> Result<String, EmailError> {
-    if &content_header[..=35].to_lowercase() != "content-type: multipart/alternative;" {
+    let lowercase = |range: std::ops::RangeInclusive<usize>| {
+        content_header.get(range).map(|text| text.to_lowercase())
+    };
+    if lowercase(0..=35).as_deref() != Some("content-type: multipart/alternative;") {
         return Err(EmailError::InvalidContentType);
     }
 
-    if &content_header[37..=45].to_lowercase() != "boundary=" {
+    if lowercase(37..=45).as_deref() != Some("boundary=") {
         return Err(EmailError::BoundaryParameterMissing);
     }
 
@@ -45,7 +173,7 @@ fn parse_content_type(content_header: String) -> Result<String, EmailError> {
         let this_char = content_header.as_bytes()[index] as char;
 
         let end_of_boundary =
-            (!quoted && !escaped && this_char == ';') || (quoted && !escaped && this_char == '"');
+            !escaped && ((!quoted && this_char == ';') || (quoted && this_char == '"'));
 
         if end_of_boundary {
             break;
@@ -125,10 +253,7 @@ fn parse_mime_from_body(body: String, boundary_parameter: String) -> String {
             if lowered_line.starts_with("content-transfer-encoding:") {
                 let encoding = &lowered_line[27..lowered_line.len()];
 
-                valid_transfer_encoding = match encoding {
-                    "quoted-printable" | "7bit" | "8bit" => true,
-                    _ => false,
-                };
+                valid_transfer_encoding = matches!(encoding, "quoted-printable" | "7bit" | "8bit");
 
                 if !valid_transfer_encoding {
                     valid_content_type = false;
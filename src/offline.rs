@@ -0,0 +1,405 @@
+use crate::attachments::attachments;
+use crate::cache::{CachedMessage, FolderCache};
+use crate::date::{search_date, DateTime};
+use crate::email_error::EmailError;
+use crate::fetch::format_message;
+use crate::helper::SequenceSet;
+use crate::list::list_entry;
+use crate::mime::{check_mime_version, mime_from_body, mime_text, MimeOptions};
+use crate::parse::{format_header, HeaderFormat};
+use crate::structure::format_structure;
+use std::cell::OnceCell;
+
+// Answers read-only commands from a folder's local cache instead of the server.
+// Sequence numbers count the cached UIDs in ascending order, as the server does.
+pub struct Offline {
+    cache: FolderCache,
+}
+
+impl Offline {
+    pub fn open(root: &str, account: &str, folder: &str) -> Result<Offline, EmailError> {
+        let cache = FolderCache::open_existing(root, account, folder)?;
+        Ok(Offline { cache })
+    }
+
+    fn cached(&self, message_num: u32) -> Result<(u32, &CachedMessage), EmailError> {
+        message_num
+            .checked_sub(1)
+            .and_then(|index| self.cache.messages.iter().nth(index as usize))
+            .map(|(uid, cached)| (*uid, cached))
+            .ok_or(EmailError::MessageNotCached)
+    }
+
+    fn message(&self, message_num: u32) -> Result<Vec<u8>, EmailError> {
+        self.cache.read_message(self.cached(message_num)?.0)
+    }
+
+    pub fn fetch(&self, message_num: u32) -> Result<String, EmailError> {
+        Ok(format_message(&self.message(message_num)?))
+    }
+
+    pub fn parse(&self, message_num: u32, format: &HeaderFormat) -> Result<String, EmailError> {
+        let internal_date = &self.cached(message_num)?.1.internal_date;
+        format_header(&self.message(message_num)?, Some(internal_date), format)
+    }
+
+    pub fn mime(&self, message_num: u32, options: &MimeOptions) -> Result<String, EmailError> {
+        let message = self.message(message_num)?;
+        if !options.raw {
+            return mime_text(&message, options);
+        }
+        let unwrapped = |field: &str| {
+            field_lines(&message, field)
+                .iter()
+                .map(|line| line.trim())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        };
+
+        check_mime_version(&unwrapped("MIME-VERSION"))?;
+        mime_from_body(unwrapped("CONTENT-TYPE"), format_message(&message))
+    }
+
+    pub fn structure(&self, message_num: u32) -> Result<String, EmailError> {
+        Ok(format_structure(&self.message(message_num)?))
+    }
+
+    pub fn attachments(
+        &self,
+        message_num: u32,
+        output: &str,
+        parts: &str,
+    ) -> Result<String, EmailError> {
+        attachments(&self.message(message_num)?, output, parts)
+    }
+
+    pub fn list(&self, raw: bool) -> Result<String, EmailError> {
+        let mut response = String::new();
+        for (index, uid) in self.cache.messages.keys().enumerate() {
+            let message = self.cache.read_message(*uid)?;
+            let subject_line = field_lines(&message, "SUBJECT").concat();
+            response.push_str(&list_entry(&(index + 1).to_string(), &subject_line, raw));
+        }
+        Ok(response)
+    }
+
+    pub fn search(&self, criteria: &str) -> Result<String, EmailError> {
+        let criteria = if criteria.is_empty() { "ALL" } else { criteria };
+        let mut parser = SearchParser {
+            tokens: tokenize(criteria)?,
+            position: 0,
+            last: self.cache.messages.len() as u32,
+            last_uid: self.cache.messages.keys().last().copied().unwrap_or(0),
+        };
+        let search = parser.all_keys()?;
+        if parser.position != parser.tokens.len() {
+            return Err(EmailError::InvalidSearch);
+        }
+
+        let mut response = String::new();
+        for (index, (uid, cached)) in self.cache.messages.iter().enumerate() {
+            let candidate = Candidate {
+                sequence: index as u32 + 1,
+                uid: *uid,
+                cached,
+                cache: &self.cache,
+                message: OnceCell::new(),
+            };
+            if candidate.matches(&search)? {
+                response.push_str(&format!("{}\n", uid));
+            }
+        }
+        Ok(response)
+    }
+}
+
+// Returns the lines of every header field with the given name, folded lines included,
+// as the server returns them for BODY[HEADER.FIELDS (name)]. 8-bit bytes are read
+// lossily, as the online commands read the server's response.
+fn field_lines(message: &[u8], field: &str) -> Vec<String> {
+    let header_end = message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(message.len(), |end| end + 2);
+    let header = String::from_utf8_lossy(&message[..header_end]);
+
+    let mut lines = Vec::new();
+    let mut in_field = false;
+    for line in header.split("\r\n") {
+        if line.starts_with([' ', '\t']) {
+            if in_field {
+                lines.push(line.to_string());
+            }
+            continue;
+        }
+        in_field = line
+            .split_once(':')
+            .is_some_and(|(name, _)| name.trim_end().eq_ignore_ascii_case(field));
+        if in_field {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+// The subset of IMAP SEARCH (RFC 3501 section 6.4.4) that can be answered from the cache
+enum SearchKey {
+    All,
+    Flag(String, bool),
+    Header(String, String),
+    Body(String),
+    Text(String),
+    Larger(u64),
+    Smaller(u64),
+    Before(i64),
+    On(i64),
+    Since(i64),
+    SentBefore(i64),
+    SentOn(i64),
+    SentSince(i64),
+    Sequence(SequenceSet),
+    Uid(SequenceSet),
+    Not(Box<SearchKey>),
+    Or(Box<SearchKey>, Box<SearchKey>),
+    And(Vec<SearchKey>),
+}
+
+// Splits criteria into atoms, quoted strings and parentheses
+fn tokenize(criteria: &str) -> Result<Vec<String>, EmailError> {
+    let mut tokens = Vec::new();
+    let mut chars = criteria.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => {
+                chars.next();
+            }
+            '(' | ')' => {
+                tokens.push(c.to_string());
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(EmailError::InvalidSearch),
+                    }
+                }
+                // Quoted tokens are marked so they are never mistaken for keys
+                tokens.push(format!("\"{}", value));
+            }
+            _ => {
+                let mut atom = String::new();
+                while let Some(&c) = chars.peek() {
+                    if matches!(c, ' ' | '(' | ')') {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                tokens.push(atom);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct SearchParser {
+    tokens: Vec<String>,
+    position: usize,
+    // Highest sequence number and UID, which * stands for
+    last: u32,
+    last_uid: u32,
+}
+
+impl SearchParser {
+    fn next(&mut self) -> Result<String, EmailError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(EmailError::InvalidSearch)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn string(&mut self) -> Result<String, EmailError> {
+        let token = self.next()?;
+        Ok(token.strip_prefix('"').unwrap_or(&token).to_string())
+    }
+
+    fn number(&mut self) -> Result<u64, EmailError> {
+        self.string()?
+            .parse()
+            .map_err(|_| EmailError::InvalidSearch)
+    }
+
+    fn date(&mut self) -> Result<i64, EmailError> {
+        search_date(&self.string()?).ok_or(EmailError::InvalidSearch)
+    }
+
+    // Keys listed one after another must all match
+    fn all_keys(&mut self) -> Result<SearchKey, EmailError> {
+        let mut keys = Vec::new();
+        while self.position < self.tokens.len() && self.tokens[self.position] != ")" {
+            keys.push(self.key()?);
+        }
+        Ok(SearchKey::And(keys))
+    }
+
+    fn key(&mut self) -> Result<SearchKey, EmailError> {
+        let token = self.next()?;
+        let flag = |name: &str, set: bool| Ok(SearchKey::Flag(name.to_string(), set));
+        let header = |name: &str, parser: &mut SearchParser| {
+            Ok(SearchKey::Header(name.to_string(), parser.string()?))
+        };
+
+        match token.to_uppercase().as_str() {
+            "(" => {
+                let keys = self.all_keys()?;
+                if self.next()? != ")" {
+                    return Err(EmailError::InvalidSearch);
+                }
+                Ok(keys)
+            }
+            "ALL" => Ok(SearchKey::All),
+            "SEEN" => flag("\\Seen", true),
+            "UNSEEN" => flag("\\Seen", false),
+            "ANSWERED" => flag("\\Answered", true),
+            "UNANSWERED" => flag("\\Answered", false),
+            "FLAGGED" => flag("\\Flagged", true),
+            "UNFLAGGED" => flag("\\Flagged", false),
+            "DELETED" => flag("\\Deleted", true),
+            "UNDELETED" => flag("\\Deleted", false),
+            "DRAFT" => flag("\\Draft", true),
+            "UNDRAFT" => flag("\\Draft", false),
+            "KEYWORD" => Ok(SearchKey::Flag(self.string()?, true)),
+            "UNKEYWORD" => Ok(SearchKey::Flag(self.string()?, false)),
+            "FROM" => header("FROM", self),
+            "TO" => header("TO", self),
+            "CC" => header("CC", self),
+            "BCC" => header("BCC", self),
+            "SUBJECT" => header("SUBJECT", self),
+            "HEADER" => {
+                let name = self.string()?;
+                header(&name, self)
+            }
+            "BODY" => Ok(SearchKey::Body(self.string()?)),
+            "TEXT" => Ok(SearchKey::Text(self.string()?)),
+            "LARGER" => Ok(SearchKey::Larger(self.number()?)),
+            "SMALLER" => Ok(SearchKey::Smaller(self.number()?)),
+            "BEFORE" => Ok(SearchKey::Before(self.date()?)),
+            "ON" => Ok(SearchKey::On(self.date()?)),
+            "SINCE" => Ok(SearchKey::Since(self.date()?)),
+            "SENTBEFORE" => Ok(SearchKey::SentBefore(self.date()?)),
+            "SENTON" => Ok(SearchKey::SentOn(self.date()?)),
+            "SENTSINCE" => Ok(SearchKey::SentSince(self.date()?)),
+            "UID" => {
+                let set = self.string()?;
+                Ok(SearchKey::Uid(SequenceSet::parse(&set, self.last_uid)))
+            }
+            "NOT" => Ok(SearchKey::Not(Box::new(self.key()?))),
+            "OR" => {
+                let first = self.key()?;
+                let second = self.key()?;
+                Ok(SearchKey::Or(Box::new(first), Box::new(second)))
+            }
+            set if set.starts_with(|c: char| c.is_ascii_digit() || c == '*') => {
+                Ok(SearchKey::Sequence(SequenceSet::parse(set, self.last)))
+            }
+            _ => Err(EmailError::InvalidSearch),
+        }
+    }
+}
+
+// One cached message being searched, read from disk only when a key needs its contents
+struct Candidate<'c> {
+    sequence: u32,
+    uid: u32,
+    cached: &'c CachedMessage,
+    cache: &'c FolderCache,
+    message: OnceCell<Vec<u8>>,
+}
+
+impl<'c> Candidate<'c> {
+    fn message(&self) -> Result<&[u8], EmailError> {
+        if let Some(message) = self.message.get() {
+            return Ok(message);
+        }
+        let message = self.cache.read_message(self.uid)?;
+        Ok(self.message.get_or_init(|| message))
+    }
+
+    fn body(&self) -> Result<&[u8], EmailError> {
+        let message = self.message()?;
+        let start = message
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(message.len(), |end| end + 4);
+        Ok(&message[start..])
+    }
+
+    fn internal_day(&self) -> Option<i64> {
+        DateTime::from_internal_date(&self.cached.internal_date).map(|date| date.day_number())
+    }
+
+    fn sent_day(&self) -> Result<Option<i64>, EmailError> {
+        let date = field_lines(self.message()?, "DATE").concat();
+        Ok(date
+            .split_once(':')
+            .and_then(|(_, value)| DateTime::from_header(value))
+            .map(|date| date.day_number()))
+    }
+
+    fn matches(&self, key: &SearchKey) -> Result<bool, EmailError> {
+        let matched = match key {
+            SearchKey::All => true,
+            SearchKey::Flag(name, set) => {
+                self.cached
+                    .flags
+                    .iter()
+                    .any(|flag| flag.eq_ignore_ascii_case(name))
+                    == *set
+            }
+            SearchKey::Header(name, value) => field_lines(self.message()?, name)
+                .concat()
+                .split_once(':')
+                .is_some_and(|(_, header)| contains(header.as_bytes(), value)),
+            SearchKey::Body(value) => contains(self.body()?, value),
+            SearchKey::Text(value) => contains(self.message()?, value),
+            SearchKey::Larger(size) => self.message()?.len() as u64 > *size,
+            SearchKey::Smaller(size) => (self.message()?.len() as u64) < *size,
+            SearchKey::Before(day) => self.internal_day().is_some_and(|date| date < *day),
+            SearchKey::On(day) => self.internal_day() == Some(*day),
+            SearchKey::Since(day) => self.internal_day().is_some_and(|date| date >= *day),
+            SearchKey::SentBefore(day) => self.sent_day()?.is_some_and(|date| date < *day),
+            SearchKey::SentOn(day) => self.sent_day()? == Some(*day),
+            SearchKey::SentSince(day) => self.sent_day()?.is_some_and(|date| date >= *day),
+            SearchKey::Sequence(set) => set.contains(self.sequence),
+            SearchKey::Uid(set) => set.contains(self.uid),
+            SearchKey::Not(key) => !self.matches(key)?,
+            SearchKey::Or(first, second) => self.matches(first)? || self.matches(second)?,
+            SearchKey::And(keys) => {
+                for key in keys {
+                    if !self.matches(key)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+        };
+        Ok(matched)
+    }
+}
+
+// Case-insensitive substring match, as SEARCH does for strings
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    let needle = needle.as_bytes();
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window.eq_ignore_ascii_case(needle))
+}
@@ -1,18 +1,27 @@
+use crate::address::parse_addresses;
+use crate::date::format_date;
 use crate::email_error::EmailError;
 use crate::file;
+use crate::header::{decode_words, Header};
+use crate::response::parse_fetches;
 use crate::Server;
 
 impl<'a> Server<'a> {
-    pub fn parse(&mut self, message_num: u32) -> Result<String, EmailError> {
-        let fields = ["FROM", "TO", "DATE", "SUBJECT"];
-        let mut header = String::new();
-
-        // Loop through all of the fields, send a command and parse it into a header string
-        for field in &fields {
-            let line = self.parse_command(message_num, field)?;
-            header.push_str(&line);
-            header.push('\n');
+    pub fn parse(&mut self, message_num: u32, format: &HeaderFormat) -> Result<String, EmailError> {
+        // The whole header in one round trip rather than a FETCH per field, with the
+        // INTERNALDATE in case the Date field cannot be read
+        let command = format!("FETCH {} (INTERNALDATE BODY.PEEK[HEADER])", message_num);
+        let response = self.run_command_bytes(&command)?;
+        if !self.valid_response_bytes(&response) {
+            return Err(EmailError::MessageNotFound);
         }
+        let fetch = parse_fetches(&response)
+            .into_iter()
+            .find(|fetch| fetch.get("BODY[HEADER]").is_some())
+            .ok_or(EmailError::MessageNotFound)?;
+        let internal_date = fetch.text("INTERNALDATE");
+        let block = fetch.get("BODY[HEADER]").unwrap_or_default();
+        let header = format_header(block, Some(&internal_date), format)?;
 
         // Print out the header to file and/or std out
         if self.debug {
@@ -20,30 +29,205 @@ impl<'a> Server<'a> {
         }
         Ok(header)
     }
+}
+
+pub const PARSE_FIELDS: [&str; 4] = ["FROM", "TO", "DATE", "SUBJECT"];
+
+// Fields holding address lists, which normalizing rewrites
+const ADDRESS_FIELDS: [&str; 6] = ["FROM", "TO", "CC", "BCC", "REPLY-TO", "SENDER"];
+
+// How parse shows the fields. It shows PARSE_FIELDS unless others are chosen or every
+// field is asked for. Encoded words are decoded unless the raw form is asked for,
+// normalizing rewrites address fields as a list of "Name <local@domain>", and the date
+// is shown in one of date::DATE_FORMATS.
+pub struct HeaderFormat<'f> {
+    pub fields: &'f [String],
+    pub all_fields: bool,
+    pub raw: bool,
+    pub normalize: bool,
+    pub date_format: &'f str,
+}
+
+// Formats the fields parse prints from a raw header block, one unfolded line each
+pub fn format_header(
+    block: &[u8],
+    internal_date: Option<&str>,
+    format: &HeaderFormat,
+) -> Result<String, EmailError> {
+    let header = Header::parse(block);
+    if header.fields().is_empty() && !block.trim_ascii().is_empty() {
+        return Err(EmailError::InvalidHeader);
+    }
+
+    let mut output = String::new();
+    if format.all_fields {
+        for field in header.fields() {
+            let value = format_value(&field.name, Some(&field.value), internal_date, format);
+            output.push_str(&format!("{}:{}\n", field.name, value.unwrap_or_default()));
+        }
+        return Ok(output);
+    }
 
-    fn parse_command(&mut self, message_num: u32, field: &str) -> Result<String, EmailError> {
-        let header = self.fetch_header(message_num, field)?;
-        if header.is_empty() {
-            match field {
-                "FROM" => Ok("From:".to_string()),
-                "TO" => Ok("To:".to_string()),
-                "DATE" => Ok("Date:".to_string()),
-                "SUBJECT" => Ok("Subject: <No subject>".to_string()),
-                _ => Ok(String::new()),
+    if format.fields.is_empty() {
+        for field in &PARSE_FIELDS {
+            let value = format_value(field, header.get(field), internal_date, format);
+            output.push_str(&format_field(field, value.as_deref()));
+            output.push('\n');
+        }
+        return Ok(output);
+    }
+
+    // Chosen fields are named as in the message, with a line for each time they appear
+    for name in format.fields {
+        let mut found = false;
+        for field in header.fields() {
+            if field.name.eq_ignore_ascii_case(name) {
+                found = true;
+                let value = format_value(name, Some(&field.value), internal_date, format);
+                output.push_str(&format!("{}:{}\n", field.name, value.unwrap_or_default()));
             }
+        }
+        if !found {
+            let value = format_value(name, None, internal_date, format);
+            output.push_str(&format_field(&name.to_uppercase(), value.as_deref()));
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
+
+fn format_value(
+    name: &str,
+    value: Option<&str>,
+    internal_date: Option<&str>,
+    format: &HeaderFormat,
+) -> Option<String> {
+    let name = name.to_uppercase();
+    if name == "DATE" {
+        return format_date(value, internal_date, format.date_format);
+    }
+    value.map(|value| {
+        if format.normalize && ADDRESS_FIELDS.contains(&name.as_str()) {
+            format!(" {}", normalize_addresses(value))
+        } else if format.raw {
+            value.to_string()
         } else {
-            let parts:  Vec<&str> = header.splitn(2, ": ").collect();
-            if parts.len() != 2 {
-                return Err(EmailError::InvalidHeader)
-            }
-            let command = parts[0];
-            let message = parts[1].trim_start();
-            let formatted_command = format!("{}{}",
-                command.chars().next().unwrap_or_default().to_uppercase(),
-                command.chars().skip(1).collect::<String>().to_lowercase());
+            decode_words(value)
+        }
+    })
+}
+
+// Formats one field, filling in a placeholder when the field is absent. The name is
+// capitalized after each hyphen, as in Reply-To.
+pub fn format_field(field: &str, value: Option<&str>) -> String {
+    let name = field
+        .split('-')
+        .map(|word| {
+            format!(
+                "{}{}",
+                word.chars().next().unwrap_or_default().to_uppercase(),
+                word.chars().skip(1).collect::<String>().to_lowercase()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+
+    match value {
+        Some(value) => format!("{}:{}", name, value),
+        None if field == "SUBJECT" => "Subject: <No subject>".to_string(),
+        None => format!("{}:", name),
+    }
+}
+
+fn normalize_addresses(value: &str) -> String {
+    parse_addresses(value)
+        .iter()
+        .map(|mailbox| mailbox.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &[u8] = b"From test@example.com Sat Jan  3 01:05:34 1996\r\n\
+Received: from a\r\n\
+Subject : folded\r\n\tsubject\r\n\
+from: Alice <alice@example.com>\r\n\
+Received: from b\r\n\
+X-Custom: =?UTF-8?Q?caf=C3=A9?=\r\n\
+\r\n\
+Body: not a field\r\n";
 
-            let formatted_header = format!("{}: {}", formatted_command, message);
-            Ok(formatted_header)
+    fn format<'f>(fields: &'f [String]) -> HeaderFormat<'f> {
+        HeaderFormat {
+            fields,
+            all_fields: false,
+            raw: false,
+            normalize: false,
+            date_format: "",
         }
     }
+
+    #[test]
+    fn shows_the_default_fields_with_placeholders() {
+        let output = format_header(BLOCK, None, &format(&[])).unwrap();
+        assert_eq!(
+            output,
+            "From: Alice <alice@example.com>\nTo:\nDate:\nSubject: folded\tsubject\n"
+        );
+        let output = format_header(b"To: b@example.com\r\n\r\n", None, &format(&[])).unwrap();
+        assert_eq!(
+            output,
+            "From:\nTo: b@example.com\nDate:\nSubject: <No subject>\n"
+        );
+    }
+
+    #[test]
+    fn shows_chosen_fields_each_time_they_appear() {
+        let fields = [
+            "received".to_string(),
+            "X-Custom".to_string(),
+            "Cc".to_string(),
+        ];
+        let output = format_header(BLOCK, None, &format(&fields)).unwrap();
+        assert_eq!(
+            output,
+            "Received: from a\nReceived: from b\nX-Custom: café\nCc:\n"
+        );
+    }
+
+    #[test]
+    fn shows_every_field_raw_when_asked() {
+        let format = HeaderFormat {
+            all_fields: true,
+            raw: true,
+            ..format(&[])
+        };
+        let output = format_header(BLOCK, None, &format).unwrap();
+        assert_eq!(
+            output,
+            "Received: from a\nSubject: folded\tsubject\nfrom: Alice <alice@example.com>\n\
+Received: from b\nX-Custom: =?UTF-8?Q?caf=C3=A9?=\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_address_fields() {
+        let fields = ["To".to_string()];
+        let format = HeaderFormat {
+            normalize: true,
+            ..format(&fields)
+        };
+        let block = b"To: b@EXAMPLE.com (Bob),\r\n  undisclosed:;\r\n\r\n";
+        let output = format_header(block, None, &format).unwrap();
+        assert_eq!(output, "To: Bob <b@example.com>\n");
+    }
+
+    #[test]
+    fn rejects_a_header_without_fields() {
+        assert!(format_header(b"no fields here\r\n", None, &format(&[])).is_err());
+        assert!(format_header(b"", None, &format(&[])).is_ok());
+    }
 }
@@ -0,0 +1,543 @@
+use crate::charset;
+use crate::header::{decode_words, valid_name, Header};
+use std::ops::Range;
+
+// Deeper nesting than this is treated as an opaque body rather than recursed into
+const MAX_DEPTH: usize = 32;
+
+// One node of a message's MIME tree (RFC 2045 and 2046). Multiparts have a child per
+// body part, and message/rfc822 has the encapsulated message as its only child.
+pub struct Part {
+    pub header: Header,
+    pub content_type: ContentType,
+    // Where the body is in the whole message, after the blank line that ends the header
+    pub body: Range<usize>,
+    pub children: Vec<Part>,
+}
+
+// A Content-Type such as text/plain; charset="utf-8", with the type lowercased
+pub struct ContentType {
+    pub mime_type: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContentType {
+    // Reads the type and its parameters, ignoring comments. A missing or malformed type
+    // is the default given.
+    pub fn parse(value: Option<&str>, default: &str) -> ContentType {
+        let (mime_type, parameters) = value.map(parse_parameters).unwrap_or_default();
+        let valid = mime_type
+            .split_once('/')
+            .is_some_and(|(main, sub)| !main.is_empty() && !sub.is_empty());
+        ContentType {
+            mime_type: if valid {
+                mime_type.to_lowercase()
+            } else {
+                default.to_string()
+            },
+            parameters,
+        }
+    }
+
+    // A parameter's value by its case-insensitive name
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+
+    pub fn is_message(&self) -> bool {
+        matches!(self.mime_type.as_str(), "message/rfc822" | "message/global")
+    }
+}
+
+impl Part {
+    // Builds the tree of a whole message
+    pub fn parse(message: &[u8]) -> Part {
+        parse_part(message, 0..message.len(), "text/plain", 0)
+    }
+
+    // The part with a section number such as 1.2
+    pub fn find(&self, number: &str) -> Option<&Part> {
+        self.numbered()
+            .into_iter()
+            .find(|numbered| numbered.number == number)
+            .map(|numbered| numbered.part)
+    }
+
+    // The file name the sender gave, from Content-Disposition or else the older name
+    // parameter of Content-Type
+    pub fn filename(&self) -> Option<String> {
+        let disposition = self
+            .header
+            .get("Content-Disposition")
+            .map(parse_parameters)
+            .unwrap_or_default();
+        extended_parameter(&disposition.1, "filename")
+            .or_else(|| extended_parameter(&self.content_type.parameters, "name"))
+            .filter(|filename| !filename.trim().is_empty())
+    }
+
+    // Every part in order with its IMAP section number (RFC 3501 section 6.4.5) and its
+    // depth in the tree. A multipart that is a whole message, or the body of a
+    // message/rfc822, has no number of its own, while a single part message is 1.
+    pub fn numbered(&self) -> Vec<Numbered<'_>> {
+        let mut parts = Vec::new();
+        number_message(self, "", 0, &mut parts);
+        parts
+    }
+}
+
+pub struct Numbered<'p> {
+    pub number: String,
+    pub depth: usize,
+    pub part: &'p Part,
+}
+
+fn number_message<'p>(root: &'p Part, prefix: &str, depth: usize, parts: &mut Vec<Numbered<'p>>) {
+    if root.content_type.is_multipart() {
+        parts.push(Numbered {
+            number: String::new(),
+            depth,
+            part: root,
+        });
+        number_children(root, prefix, depth + 1, parts);
+    } else {
+        number_part(root, &join(prefix, 1), depth, parts);
+    }
+}
+
+fn number_part<'p>(part: &'p Part, number: &str, depth: usize, parts: &mut Vec<Numbered<'p>>) {
+    parts.push(Numbered {
+        number: number.to_string(),
+        depth,
+        part,
+    });
+    if part.content_type.is_multipart() {
+        number_children(part, number, depth + 1, parts);
+    } else if let Some(message) = part.children.first() {
+        number_message(message, number, depth + 1, parts);
+    }
+}
+
+fn number_children<'p>(part: &'p Part, prefix: &str, depth: usize, parts: &mut Vec<Numbered<'p>>) {
+    for (index, child) in part.children.iter().enumerate() {
+        number_part(child, &join(prefix, index + 1), depth, parts);
+    }
+}
+
+fn join(prefix: &str, index: usize) -> String {
+    if prefix.is_empty() {
+        index.to_string()
+    } else {
+        format!("{}.{}", prefix, index)
+    }
+}
+
+fn parse_part(message: &[u8], range: Range<usize>, default_type: &str, depth: usize) -> Part {
+    let (header_end, body_start) = header_end(&message[range.clone()]);
+    let header = Header::parse(&message[range.start..range.start + header_end]);
+    let content_type = ContentType::parse(header.get("Content-Type"), default_type);
+    let body = range.start + body_start..range.end;
+
+    let mut children = Vec::new();
+    if depth < MAX_DEPTH {
+        if let (true, Some(boundary)) = (
+            content_type.is_multipart(),
+            content_type.parameter("boundary"),
+        ) {
+            // Parts of a digest are messages unless they say otherwise
+            let child_type = if content_type.mime_type == "multipart/digest" {
+                "message/rfc822"
+            } else {
+                "text/plain"
+            };
+            for child in split_multipart(message, body.clone(), boundary.as_bytes()) {
+                children.push(parse_part(message, child, child_type, depth + 1));
+            }
+        } else if content_type.is_message() {
+            children.push(parse_part(message, body.clone(), "text/plain", depth + 1));
+        }
+    }
+
+    Part {
+        header,
+        content_type,
+        body,
+        children,
+    }
+}
+
+// Finds the blank line that ends a header, returning where the header ends and where
+// the body starts. A part that starts with a blank line has no header, and so does
+// one that starts with something other than a field, which some senders write. With
+// no blank line at all the part is all header.
+fn header_end(part: &[u8]) -> (usize, usize) {
+    if part.starts_with(b"\r\n") {
+        return (0, 2);
+    }
+    if part.starts_with(b"\n") {
+        return (0, 1);
+    }
+    let first_line = part.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    let starts_with_field = first_line
+        .iter()
+        .position(|&byte| byte == b':')
+        .is_some_and(|colon| {
+            valid_name(String::from_utf8_lossy(&first_line[..colon]).trim_end_matches([' ', '\t']))
+        });
+    if !starts_with_field {
+        return (0, 0);
+    }
+    let mut position = 0;
+    while let Some(offset) = part[position..].iter().position(|&byte| byte == b'\n') {
+        let line_end = position + offset + 1;
+        let rest = &part[line_end..];
+        if rest.starts_with(b"\r\n") {
+            return (line_end, line_end + 2);
+        }
+        if rest.starts_with(b"\n") {
+            return (line_end, line_end + 1);
+        }
+        position = line_end;
+    }
+    (part.len(), part.len())
+}
+
+// Splits a multipart body into the ranges of its parts. The preamble before the first
+// delimiter and the epilogue after the close delimiter are dropped, delimiter lines may
+// have transport padding after them, and the line break before a delimiter belongs to
+// the delimiter (RFC 2046 section 5.1.1). Without a close delimiter the last part runs
+// to the end of the body.
+fn split_multipart(message: &[u8], body: Range<usize>, boundary: &[u8]) -> Vec<Range<usize>> {
+    let mut parts = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut position = body.start;
+
+    while position < body.end {
+        let line_end = message[position..body.end]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(body.end, |offset| position + offset + 1);
+        let line = &message[position..line_end];
+
+        if let Some(closing) = delimiter(line, boundary) {
+            if let Some(start) = current {
+                // The delimiter's line break may be CRLF or a bare LF
+                let mut end = position.max(start);
+                if end > start && message[end - 1] == b'\n' {
+                    end -= 1;
+                    if end > start && message[end - 1] == b'\r' {
+                        end -= 1;
+                    }
+                }
+                parts.push(start..end);
+            }
+            if closing {
+                return parts;
+            }
+            current = Some(line_end);
+        }
+        position = line_end;
+    }
+
+    if let Some(start) = current {
+        parts.push(start..body.end);
+    }
+    parts
+}
+
+// Whether a line is a delimiter for the boundary, and if so whether it is the close
+// delimiter
+fn delimiter(line: &[u8], boundary: &[u8]) -> Option<bool> {
+    let rest = line.strip_prefix(b"--")?.strip_prefix(boundary)?;
+    let (closing, padding) = match rest.strip_prefix(b"--") {
+        Some(padding) => (true, padding),
+        None => (false, rest),
+    };
+    padding
+        .iter()
+        .all(|byte| matches!(byte, b' ' | b'\t' | b'\r' | b'\n'))
+        .then_some(closing)
+}
+
+// Splits a structured field such as Content-Type or Content-Disposition into its value
+// and parameters, unquoting quoted strings and dropping comments
+pub fn parse_parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let mut pieces: Vec<String> = vec![String::new()];
+    let mut chars = value.chars();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        let piece = pieces.last_mut().unwrap();
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth > 0 => (),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => piece.extend(chars.next()),
+                        '"' => break,
+                        c => piece.push(c),
+                    }
+                }
+            }
+            ';' => pieces.push(String::new()),
+            c if c.is_whitespace() => (),
+            c => piece.push(c),
+        }
+    }
+
+    let mut pieces = pieces.into_iter();
+    let value = pieces.next().unwrap_or_default();
+    let parameters = pieces
+        .filter_map(|piece| {
+            let (name, value) = piece.split_once('=')?;
+            Some((name.to_lowercase(), value.to_string()))
+        })
+        .collect();
+    (value, parameters)
+}
+
+// A parameter's value with RFC 2231 extensions undone. Values may be split into
+// numbered sections (name*0, name*1, ...) and those marked with a trailing * are
+// percent-encoded, the first starting with the charset and language, as in
+// name*=utf-8'en'%E2%82%AC.pdf. Plain values are decoded as RFC 2047 encoded words,
+// which many senders use in parameters although it is not allowed there.
+pub fn extended_parameter(parameters: &[(String, String)], name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    let mut sections: Vec<(u32, bool, &str)> = parameters
+        .iter()
+        .filter_map(|(key, value)| {
+            // name* is a single encoded section, otherwise sections are numbered
+            let suffix = key.strip_prefix(name.as_str())?;
+            let (index, encoded) = if suffix == "*" {
+                (0, true)
+            } else {
+                let rest = suffix.strip_prefix('*')?;
+                match rest.strip_suffix('*') {
+                    Some(index) => (index.parse().ok()?, true),
+                    None => (rest.parse().ok()?, false),
+                }
+            };
+            Some((index, encoded, value.as_str()))
+        })
+        .collect();
+    if sections.is_empty() {
+        return parameters
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| decode_words(value));
+    }
+    sections.sort_by_key(|section| section.0);
+    sections.dedup_by_key(|section| section.0);
+
+    // Sections are joined as bytes and decoded together, stopping at any gap
+    let mut charset = None;
+    let mut bytes = Vec::new();
+    for (position, &(index, encoded, value)) in sections.iter().enumerate() {
+        if index as usize != position {
+            break;
+        }
+        let mut value = value;
+        if index == 0 && encoded {
+            if let Some((declared, rest)) = value.split_once('\'') {
+                let (_language, rest) = rest.split_once('\'').unwrap_or(("", rest));
+                charset = Some(declared).filter(|declared| !declared.is_empty());
+                value = rest;
+            }
+        }
+        if encoded {
+            percent_decode(value, &mut bytes);
+        } else {
+            bytes.extend_from_slice(value.as_bytes());
+        }
+    }
+    Some(
+        charset
+            .and_then(|charset| charset::decode(&bytes, charset))
+            .unwrap_or_else(|| charset::to_utf8(&bytes, None).text),
+    )
+}
+
+// A % not followed by two hex digits is kept as it is
+fn percent_decode(value: &str, output: &mut Vec<u8>) {
+    let bytes = value.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| bytes.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|digits| {
+                let high = (digits[0] as char).to_digit(16)?;
+                let low = (digits[1] as char).to_digit(16)?;
+                Some((high * 16 + low) as u8)
+            });
+        match escaped {
+            Some(byte) => {
+                output.push(byte);
+                index += 3;
+            }
+            None => {
+                output.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NESTED: &[u8] = b"Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+\r\n\
+This preamble is dropped\r\n\
+--outer  \r\n\
+Content-Type: multipart/alternative; boundary=inner\r\n\
+\r\n\
+--inner\r\n\
+\r\n\
+plain\r\n\
+--inner\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<p>html</p>\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: message/rfc822\r\n\
+\r\n\
+Subject: inside\r\n\
+Content-Type: multipart/mixed; boundary=deep\r\n\
+\r\n\
+--deep\r\n\
+Content-Type: image/png; name=\"a.png\"\r\n\
+\r\n\
+png\r\n\
+--deep--\r\n\
+--outer--\r\n\
+and this epilogue too\r\n";
+
+    fn body<'m>(message: &'m [u8], part: &Part) -> &'m [u8] {
+        &message[part.body.clone()]
+    }
+
+    #[test]
+    fn numbers_parts_as_imap_sections() {
+        let root = Part::parse(NESTED);
+        let numbered: Vec<(String, usize, String)> = root
+            .numbered()
+            .into_iter()
+            .map(|numbered| {
+                let mime_type = numbered.part.content_type.mime_type.clone();
+                (numbered.number, numbered.depth, mime_type)
+            })
+            .collect();
+        let expected = [
+            ("", 0, "multipart/mixed"),
+            ("1", 1, "multipart/alternative"),
+            ("1.1", 2, "text/plain"),
+            ("1.2", 2, "text/html"),
+            ("2", 1, "message/rfc822"),
+            ("", 2, "multipart/mixed"),
+            ("2.1", 3, "image/png"),
+        ];
+        assert_eq!(numbered.len(), expected.len());
+        for (found, (number, depth, mime_type)) in numbered.iter().zip(expected) {
+            assert_eq!(
+                (found.0.as_str(), found.1, found.2.as_str()),
+                (number, depth, mime_type)
+            );
+        }
+    }
+
+    #[test]
+    fn drops_preamble_epilogue_and_delimiter_line_breaks() {
+        let root = Part::parse(NESTED);
+        assert_eq!(body(NESTED, root.find("1.1").unwrap()), b"plain");
+        assert_eq!(body(NESTED, root.find("1.2").unwrap()), b"<p>html</p>");
+        assert_eq!(body(NESTED, root.find("2.1").unwrap()), b"png");
+        assert_eq!(
+            root.find("2.1").unwrap().filename().as_deref(),
+            Some("a.png")
+        );
+        assert!(root.find("3").is_none());
+    }
+
+    #[test]
+    fn runs_the_last_part_to_the_end_without_a_close_delimiter() {
+        let message =
+            b"Content-Type: multipart/mixed; boundary=b\n\n--b\n\none\n--bb\n--b\n\ntwo\n";
+        let root = Part::parse(message);
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(body(message, &root.children[0]), b"one\n--bb");
+        assert_eq!(body(message, &root.children[1]), b"two\n");
+    }
+
+    #[test]
+    fn reads_single_parts_and_digests() {
+        let root = Part::parse(b"Subject: x\r\n\r\nbody");
+        assert_eq!(root.content_type.mime_type, "text/plain");
+        assert_eq!(root.numbered()[0].number, "1");
+
+        let digest = b"Content-Type: multipart/digest; boundary=d\r\n\r\n\
+--d\r\n\r\nSubject: one\r\n\r\nfirst\r\n--d--\r\n";
+        let root = Part::parse(digest);
+        assert_eq!(root.children[0].content_type.mime_type, "message/rfc822");
+        assert_eq!(body(digest, &root.children[0].children[0]), b"first");
+    }
+
+    #[test]
+    fn treats_a_part_without_a_header_as_all_body() {
+        let message =
+            b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nno header here\r\n--b--\r\n";
+        let root = Part::parse(message);
+        assert_eq!(body(message, &root.children[0]), b"no header here");
+    }
+
+    #[test]
+    fn stops_recursing_at_the_depth_limit() {
+        let mut message = Vec::new();
+        for _ in 0..100 {
+            message.extend_from_slice(b"Content-Type: message/rfc822\r\n\r\n");
+        }
+        message.extend_from_slice(b"Subject: bottom\r\n\r\nbody");
+        let root = Part::parse(&message);
+        assert!(root.numbered().len() <= MAX_DEPTH + 2);
+    }
+
+    #[test]
+    fn parses_parameters_and_rfc_2231_extensions() {
+        let (value, parameters) =
+            parse_parameters("Attachment (a comment); filename=\"a \\\"b\\\".txt\"; SIZE=3");
+        assert_eq!(value, "Attachment");
+        assert_eq!(
+            parameters[0],
+            ("filename".to_string(), "a \"b\".txt".to_string())
+        );
+        assert_eq!(parameters[1], ("size".to_string(), "3".to_string()));
+
+        let (_, parameters) = parse_parameters(
+            "attachment; filename*0*=utf-8'en'%E2%82%AC; filename*1=\" rates.pdf\"",
+        );
+        assert_eq!(
+            extended_parameter(&parameters, "filename").as_deref(),
+            Some("€ rates.pdf")
+        );
+        let (_, parameters) = parse_parameters("attachment; filename*=''100%25%zz");
+        assert_eq!(
+            extended_parameter(&parameters, "filename").as_deref(),
+            Some("100%%zz")
+        );
+        let (_, parameters) = parse_parameters("attachment; name==?UTF-8?Q?caf=C3=A9?=");
+        assert_eq!(
+            extended_parameter(&parameters, "name").as_deref(),
+            Some("café")
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use crate::helper::SequenceSet;
+
+// One untagged FETCH response, with each attribute's value kept as raw bytes.
+// Parenthesised values keep their contents without the outer parentheses, quoted
+// strings are unquoted and literals hold exactly the bytes the server sent.
+pub struct Fetch {
+    pub attributes: Vec<(String, Vec<u8>)>,
+}
+
+impl Fetch {
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    // Takes the value out of the fetch, for callers that keep it and would otherwise copy it
+    pub fn take(&mut self, name: &str) -> Option<Vec<u8>> {
+        let index = self
+            .attributes
+            .iter()
+            .position(|(attribute, _)| attribute.eq_ignore_ascii_case(name))?;
+        Some(self.attributes.swap_remove(index).1)
+    }
+
+    pub fn text(&self, name: &str) -> String {
+        self.get(name)
+            .map(|value| String::from_utf8_lossy(value).to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn number(&self, name: &str) -> u64 {
+        self.text(name).trim().parse().unwrap_or(0)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.number("UID") as u32
+    }
+
+    pub fn flags(&self) -> Vec<String> {
+        self.text("FLAGS")
+            .split_whitespace()
+            .map(|flag| flag.to_string())
+            .collect()
+    }
+}
+
+// Collects the FETCH responses out of a full command response
+pub fn parse_fetches(response: &[u8]) -> Vec<Fetch> {
+    let mut parser = Parser {
+        input: response,
+        position: 0,
+    };
+    let mut fetches = Vec::new();
+
+    while parser.position < response.len() {
+        if let Some(fetch) = parser.fetch() {
+            fetches.push(fetch);
+        }
+        parser.skip_line();
+    }
+
+    fetches
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.input[self.position.min(self.input.len())..]
+    }
+
+    fn eat(&mut self, expected: &[u8]) -> bool {
+        let rest = self.rest();
+        if rest.len() >= expected.len() && rest[..expected.len()].eq_ignore_ascii_case(expected) {
+            self.position += expected.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.position += 1;
+        }
+    }
+
+    // Moves past the end of the current line, stepping over any literals on the way
+    fn skip_line(&mut self) {
+        while let Some(byte) = self.peek() {
+            if byte == b'{' && self.literal().is_some() {
+                continue;
+            }
+            self.position += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+
+    // * <seq> FETCH (<attribute> <value> ...)
+    fn fetch(&mut self) -> Option<Fetch> {
+        let start = self.position;
+        if !self.eat(b"* ") {
+            return None;
+        }
+        // The sequence number, UID FETCH responses carry the UID as an attribute
+        self.atom();
+        self.skip_spaces();
+        if !self.eat(b"FETCH (") {
+            self.position = start;
+            return None;
+        }
+
+        let mut attributes = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b')') => {
+                    self.position += 1;
+                    break;
+                }
+                None | Some(b'\r') | Some(b'\n') => break,
+                _ => {}
+            }
+
+            let name = String::from_utf8_lossy(&self.attribute_name()).to_uppercase();
+            self.skip_spaces();
+            let value = self.value();
+            attributes.push((name, value));
+        }
+
+        Some(Fetch { attributes })
+    }
+
+    // Reads an attribute name such as BODY[HEADER.FIELDS (SUBJECT)]<0>
+    fn attribute_name(&mut self) -> Vec<u8> {
+        let start = self.position;
+        let mut depth = 0;
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b' ' | b')' | b'\r' if depth == 0 => break,
+                _ => {}
+            }
+            self.position += 1;
+        }
+        self.input[start..self.position].to_vec()
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        match self.peek() {
+            Some(b'(') => self.list(),
+            Some(b'"') => self.quoted(),
+            Some(b'{') => self.literal().unwrap_or_default(),
+            _ => {
+                let atom = self.atom();
+                if atom.eq_ignore_ascii_case(b"NIL") {
+                    Vec::new()
+                } else {
+                    atom
+                }
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Vec<u8> {
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if matches!(byte, b' ' | b'(' | b')' | b'\r' | b'\n') {
+                break;
+            }
+            self.position += 1;
+        }
+        self.input[start..self.position].to_vec()
+    }
+
+    fn quoted(&mut self) -> Vec<u8> {
+        let mut value = Vec::new();
+        self.position += 1;
+        while let Some(byte) = self.peek() {
+            self.position += 1;
+            match byte {
+                b'\\' => {
+                    if let Some(escaped) = self.peek() {
+                        value.push(escaped);
+                        self.position += 1;
+                    }
+                }
+                b'"' => break,
+                _ => value.push(byte),
+            }
+        }
+        value
+    }
+
+    // Returns the contents of a parenthesised list, nested lists and strings included
+    fn list(&mut self) -> Vec<u8> {
+        let start = self.position + 1;
+        let mut depth = 0;
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'(' => depth += 1,
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.position += 1;
+                        return self.input[start..self.position - 1].to_vec();
+                    }
+                }
+                b'"' => {
+                    self.quoted();
+                    continue;
+                }
+                b'{' if self.literal().is_some() => continue,
+                b'\r' | b'\n' => break,
+                _ => {}
+            }
+            self.position += 1;
+        }
+        self.input[start.min(self.position)..self.position].to_vec()
+    }
+
+    // {<length>}\r\n followed by exactly that many bytes
+    fn literal(&mut self) -> Option<Vec<u8>> {
+        let rest = self.rest();
+        let close = rest.iter().position(|&byte| byte == b'}')?;
+        let length: usize = std::str::from_utf8(&rest[1..close]).ok()?.parse().ok()?;
+        if !rest[close + 1..].starts_with(b"\r\n") {
+            return None;
+        }
+
+        let start = self.position + close + 3;
+        let end = (start + length).min(self.input.len());
+        self.position = end;
+        Some(self.input[start..end].to_vec())
+    }
+}
+
+// Collects the UIDs from untagged VANISHED responses, e.g. * VANISHED (EARLIER) 41,43:116
+pub fn parse_vanished(response: &str) -> SequenceSet {
+    let sets: Vec<&str> = response
+        .lines()
+        .filter_map(|line| line.strip_prefix("* VANISHED "))
+        .map(|line| line.trim_start_matches("(EARLIER)").trim())
+        .collect();
+    SequenceSet::parse(&sets.join(","), 0)
+}
+
+// Collects the numbers from untagged SEARCH responses
+pub fn parse_search(response: &str) -> Vec<u32> {
+    response
+        .lines()
+        .filter_map(|line| line.strip_prefix("* SEARCH"))
+        .flat_map(|line| line.split_whitespace())
+        .map_while(|number| number.parse().ok())
+        .collect()
+}
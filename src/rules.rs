@@ -0,0 +1,258 @@
+use crate::address::parse_addresses;
+use crate::arguments::parse_folder;
+use crate::date::{now, DateTime};
+use crate::email_error::EmailError;
+use crate::header::Header;
+use std::fs;
+
+// A rules file has a section per rule, tried in order. Every condition in a rule must
+// hold unless it says "match = any", and its actions then run in the order given:
+//
+//   [newsletters]
+//   from = @news.example.com
+//   header = List-Id: weekly
+//   to-address = me@example.com
+//   older = 30
+//   action = flag \Seen
+//   action = move Lists/News
+//   action = stop
+//
+// Text conditions are case-insensitive substring matches, while from-address,
+// to-address and cc-address compare the bare addresses in the field, ignoring case.
+// Sizes are in bytes and ages in days, from the server's INTERNALDATE.
+pub struct Rule {
+    pub name: String,
+    any: bool,
+    conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+enum Condition {
+    Header(String, String),
+    Address(String, String),
+    Body(String),
+    Larger(u64),
+    Smaller(u64),
+    Older(i64),
+    Newer(i64),
+    Flag(String, bool),
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Move(String),
+    Copy(String),
+    Flag(String),
+    Unflag(String),
+    Delete,
+    Pipe(String),
+    Stop,
+}
+
+impl Action {
+    pub fn describe(&self) -> String {
+        match self {
+            Action::Move(folder) => format!("move {}", folder),
+            Action::Copy(folder) => format!("copy {}", folder),
+            Action::Flag(flag) => format!("flag {}", flag),
+            Action::Unflag(flag) => format!("unflag {}", flag),
+            Action::Delete => "delete".to_string(),
+            Action::Pipe(command) => format!("pipe {}", command),
+            Action::Stop => "stop".to_string(),
+        }
+    }
+}
+
+// What the rules can look at for one message. The message may be just its header
+// when no rule needs the body.
+pub struct Candidate<'m> {
+    pub flags: &'m [String],
+    pub internal_date: &'m str,
+    pub size: u64,
+    pub message: &'m [u8],
+}
+
+pub fn read_rules(path: &str) -> Result<Vec<Rule>, EmailError> {
+    let contents = fs::read_to_string(path).map_err(|_| EmailError::InvalidRules)?;
+
+    let mut rules: Vec<Rule> = Vec::new();
+    for line in contents.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            rules.push(Rule {
+                name: name.trim().to_string(),
+                any: false,
+                conditions: Vec::new(),
+                actions: Vec::new(),
+            });
+            continue;
+        }
+
+        let (Some(rule), Some((key, value))) = (rules.last_mut(), line.split_once('=')) else {
+            return Err(EmailError::InvalidRules);
+        };
+        let value = value.trim();
+        let number = || value.parse::<u64>().map_err(|_| EmailError::InvalidRules);
+        let yes = || match value {
+            "yes" | "true" => Ok(true),
+            "no" | "false" => Ok(false),
+            _ => Err(EmailError::InvalidRules),
+        };
+
+        let condition = match key.trim() {
+            "match" => {
+                rule.any = match value {
+                    "any" => true,
+                    "all" => false,
+                    _ => return Err(EmailError::InvalidRules),
+                };
+                continue;
+            }
+            "action" => {
+                rule.actions.push(parse_action(value)?);
+                continue;
+            }
+            name @ ("from" | "to" | "cc" | "subject") => {
+                Condition::Header(name.to_string(), value.to_string())
+            }
+            "from-address" | "to-address" | "cc-address" => {
+                let name = key.trim().trim_end_matches("-address");
+                Condition::Address(name.to_string(), value.to_string())
+            }
+            "header" => {
+                let (name, text) = value.split_once(':').ok_or(EmailError::InvalidRules)?;
+                Condition::Header(name.trim().to_string(), text.trim().to_string())
+            }
+            "body" => Condition::Body(value.to_string()),
+            "larger" => Condition::Larger(number()?),
+            "smaller" => Condition::Smaller(number()?),
+            "older" => Condition::Older(number()? as i64),
+            "newer" => Condition::Newer(number()? as i64),
+            "seen" => Condition::Flag("\\Seen".to_string(), yes()?),
+            "answered" => Condition::Flag("\\Answered".to_string(), yes()?),
+            "flagged" => Condition::Flag("\\Flagged".to_string(), yes()?),
+            "draft" => Condition::Flag("\\Draft".to_string(), yes()?),
+            "deleted" => Condition::Flag("\\Deleted".to_string(), yes()?),
+            "keyword" => Condition::Flag(value.to_string(), true),
+            _ => return Err(EmailError::InvalidRules),
+        };
+        rule.conditions.push(condition);
+    }
+
+    if rules.is_empty() || rules.iter().any(|rule| rule.actions.is_empty()) {
+        return Err(EmailError::InvalidRules);
+    }
+    Ok(rules)
+}
+
+fn parse_action(value: &str) -> Result<Action, EmailError> {
+    let (name, argument) = value.split_once(' ').unwrap_or((value, ""));
+    let argument = argument.trim();
+    let action = match (name, argument.is_empty()) {
+        ("move", false) => Action::Move(parse_folder(argument)),
+        ("copy", false) => Action::Copy(parse_folder(argument)),
+        ("flag", false) => Action::Flag(argument.to_string()),
+        ("unflag", false) => Action::Unflag(argument.to_string()),
+        ("pipe", false) => Action::Pipe(argument.to_string()),
+        ("delete", true) => Action::Delete,
+        ("stop", true) => Action::Stop,
+        _ => return Err(EmailError::InvalidRules),
+    };
+    if argument.contains(['\r', '\n']) {
+        return Err(EmailError::InvalidRules);
+    }
+    Ok(action)
+}
+
+// Whether any rule looks at the body or hands the message to a command, in which
+// case whole messages have to be fetched rather than headers
+pub fn needs_body(rules: &[Rule]) -> bool {
+    rules.iter().any(|rule| {
+        rule.conditions
+            .iter()
+            .any(|condition| matches!(condition, Condition::Body(_)))
+            || rule
+                .actions
+                .iter()
+                .any(|action| matches!(action, Action::Pipe(_)))
+    })
+}
+
+impl Rule {
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        let mut results = self
+            .conditions
+            .iter()
+            .map(|condition| condition.matches(candidate));
+        if self.any {
+            results.any(|result| result)
+        } else {
+            results.all(|result| result)
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, candidate: &Candidate) -> bool {
+        match self {
+            // Any of a repeated field, such as Received, can match
+            Condition::Header(name, text) => Header::parse(candidate.message)
+                .get_all(name)
+                .any(|value| value.to_lowercase().contains(&text.to_lowercase())),
+            Condition::Address(name, address) => Header::parse(candidate.message)
+                .get_all(name)
+                .flat_map(parse_addresses)
+                .any(|mailbox| mailbox.address().eq_ignore_ascii_case(address)),
+            Condition::Body(text) => {
+                let body = candidate
+                    .message
+                    .windows(4)
+                    .position(|window| window == b"\r\n\r\n")
+                    .map_or(&[][..], |end| &candidate.message[end + 4..]);
+                String::from_utf8_lossy(body)
+                    .to_lowercase()
+                    .contains(&text.to_lowercase())
+            }
+            Condition::Larger(size) => candidate.size > *size,
+            Condition::Smaller(size) => candidate.size < *size,
+            Condition::Older(days) => age_in_days(candidate).is_some_and(|age| age > *days),
+            Condition::Newer(days) => age_in_days(candidate).is_some_and(|age| age < *days),
+            Condition::Flag(flag, set) => {
+                candidate
+                    .flags
+                    .iter()
+                    .any(|candidate_flag| candidate_flag.eq_ignore_ascii_case(flag))
+                    == *set
+            }
+        }
+    }
+}
+
+fn age_in_days(candidate: &Candidate) -> Option<i64> {
+    let date = DateTime::from_internal_date(candidate.internal_date)?;
+    Some((now() - date.timestamp()) / 86400)
+}
+
+// Runs the rules over one message, returning each action with the rule it came from.
+// Processing ends at stop, and after a move or delete since the message is gone.
+pub fn evaluate<'r>(rules: &'r [Rule], candidate: &Candidate) -> Vec<(&'r str, Action)> {
+    let mut actions = Vec::new();
+    for rule in rules.iter().filter(|rule| rule.matches(candidate)) {
+        for action in &rule.actions {
+            if matches!(action, Action::Stop) {
+                return actions;
+            }
+            actions.push((rule.name.as_str(), action.clone()));
+            if matches!(action, Action::Move(_) | Action::Delete) {
+                return actions;
+            }
+        }
+    }
+    actions
+}
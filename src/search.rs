@@ -0,0 +1,51 @@
+use crate::email_error::EmailError;
+use crate::response::{parse_fetches, parse_search, Fetch};
+use crate::Server;
+
+impl<'a> Server<'a> {
+    pub fn search(&mut self, criteria: &str) -> Result<String, EmailError> {
+        let uids = self.uid_search(criteria)?;
+        Ok(uids.iter().map(|uid| format!("{}\n", uid)).collect())
+    }
+
+    // Returns the UIDs matching IMAP search criteria, or every message for an empty query
+    pub fn uid_search(&mut self, criteria: &str) -> Result<Vec<u32>, EmailError> {
+        let criteria = if criteria.is_empty() { "ALL" } else { criteria };
+        let command = format!("UID SEARCH {}", criteria);
+        let response = self.run_command(&command)?;
+        if self.valid_response(&response) {
+            Ok(parse_search(&response))
+        } else {
+            Err(EmailError::InvalidSearch)
+        }
+    }
+
+    pub fn uid_fetch(&mut self, uid_set: &str, items: &str) -> Result<Vec<Fetch>, EmailError> {
+        self.uid_fetch_with(uid_set, items, "")
+    }
+
+    // Fetches only messages whose MODSEQ is above the given one (RFC 7162)
+    pub fn uid_fetch_changed_since(
+        &mut self,
+        uid_set: &str,
+        items: &str,
+        modseq: u64,
+    ) -> Result<Vec<Fetch>, EmailError> {
+        self.uid_fetch_with(uid_set, items, &format!(" (CHANGEDSINCE {})", modseq))
+    }
+
+    fn uid_fetch_with(
+        &mut self,
+        uid_set: &str,
+        items: &str,
+        modifiers: &str,
+    ) -> Result<Vec<Fetch>, EmailError> {
+        let command = format!("UID FETCH {} ({}){}", uid_set, items, modifiers);
+        let response = self.run_command_bytes(&command)?;
+        if self.valid_response_bytes(&response) {
+            Ok(parse_fetches(&response))
+        } else {
+            Err(EmailError::MessageNotFound)
+        }
+    }
+}
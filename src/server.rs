@@ -1,14 +1,19 @@
 use crate::email_error::EmailError;
-use crate::helper::parse_string;
+use crate::helper::{fetch_attribute, literal_length, parse_string, response_code};
+use crate::journal::Journal;
 use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use std::io::ErrorKind;
 use std::io::{Read, Write};
 use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
 pub trait Shutdownable {
     fn shutdown_stream(&mut self, how: Shutdown) -> Result<(), std::io::Error>;
 }
 
-pub trait Streamable: Shutdownable + Read + Write {}
+pub trait Streamable: Shutdownable + Read + Write {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), std::io::Error>;
+}
 
 impl Shutdownable for TcpStream {
     fn shutdown_stream(&mut self, how: Shutdown) -> Result<(), std::io::Error> {
@@ -19,20 +24,63 @@ impl Shutdownable for TcpStream {
 
 impl Shutdownable for SslStream<TcpStream> {
     fn shutdown_stream(&mut self, _: Shutdown) -> Result<(), std::io::Error> {
-        self.shutdown().unwrap();
-        Ok(())
+        self.shutdown()
+            .map(|_| ())
+            .map_err(|e| e.into_io_error().unwrap_or_else(std::io::Error::other))
     }
 }
 
-impl Streamable for TcpStream {}
-impl Streamable for SslStream<TcpStream> {}
+impl Streamable for TcpStream {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.set_read_timeout(timeout)
+    }
+}
+
+impl Streamable for SslStream<TcpStream> {
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
 
 pub struct Server<'a> {
     username: &'a str,
     password: &'a str,
-    command: u8,
+    command: u32,
     stream: Option<Box<dyn Streamable>>,
+    capabilities: Option<Vec<String>>,
+    pub mailbox: Mailbox,
     pub debug: bool,
+    // Whether a missing destination folder is created without asking
+    pub create_folders: bool,
+    // Mutating commands are recorded here when set, so they can be undone
+    pub journal: Option<Journal>,
+}
+
+// What SELECT reported about the selected folder
+#[derive(Default)]
+pub struct Mailbox {
+    // As given to SELECT, so quoted if it needed to be
+    pub name: String,
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    // Zero when the server reports NOMODSEQ or lacks CONDSTORE
+    pub highest_modseq: u64,
+}
+
+impl Mailbox {
+    pub fn from_select(response: &str) -> Mailbox {
+        let code = |name| {
+            response_code(response, name)
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        Mailbox {
+            uid_validity: code("UIDVALIDITY") as u32,
+            uid_next: code("UIDNEXT") as u32,
+            highest_modseq: code("HIGHESTMODSEQ"),
+            ..Mailbox::default()
+        }
+    }
 }
 
 impl<'a> Server<'a> {
@@ -43,26 +91,31 @@ impl<'a> Server<'a> {
             password,
             command: 1,
             stream: None,
+            capabilities: None,
+            mailbox: Mailbox::default(),
             debug,
+            create_folders: false,
+            journal: None,
         }
     }
 
     // Connect method
-    pub fn connect(&mut self, mut stream: Box<dyn Streamable>) -> Result<(), EmailError> {
+    pub fn connect(&mut self, stream: Box<dyn Streamable>) -> Result<(), EmailError> {
+        self.stream = Some(stream);
+
         // Read the welcome message from the server
-        let mut buffer = [0; 512];
-        stream
-            .read(&mut buffer)
-            .expect("Failed to read from server");
+        let welcome = self.read_line()?;
         if self.debug {
-            println!("Welcome Message: {}", String::from_utf8_lossy(&buffer));
+            println!("Welcome Message: {}", String::from_utf8_lossy(&welcome));
         }
 
-        self.stream = Some(stream);
-
         Ok(())
     }
 
+    pub fn username(&self) -> &str {
+        self.username
+    }
+
     // Login method
     pub fn login(&mut self) -> Result<String, EmailError> {
         let command = format!("LOGIN {} {}", self.username, self.password);
@@ -73,22 +126,35 @@ impl<'a> Server<'a> {
         }
 
         if self.valid_response(&response) {
-            return Ok(response);
+            Ok(response)
         } else {
-            return Err(EmailError::LoginFailure);
+            Err(EmailError::LoginFailure)
         }
     }
 
     pub fn select(&mut self, folder: &str) -> Result<String, EmailError> {
-        let command = format!("SELECT {}", folder);
+        self.select_with(folder, "")
+    }
+
+    // Selects with extra parameters, such as (CONDSTORE) or (QRESYNC (...))
+    pub fn select_with(&mut self, folder: &str, parameters: &str) -> Result<String, EmailError> {
+        let command = if parameters.is_empty() {
+            format!("SELECT {}", folder)
+        } else {
+            format!("SELECT {} {}", folder, parameters)
+        };
         let response = self.run_command(&command)?;
         if self.debug {
             println!("Select Response: {}", response);
         }
         if self.valid_response(&response) {
-            return Ok(response);
+            self.mailbox = Mailbox {
+                name: folder.to_string(),
+                ..Mailbox::from_select(&response)
+            };
+            Ok(response)
         } else {
-            return Err(EmailError::FolderNotFound);
+            Err(EmailError::FolderNotFound)
         }
     }
 
@@ -105,22 +171,150 @@ impl<'a> Server<'a> {
         false
     }
 
+    // Checks only the completion line, so large message bodies are not decoded
+    pub fn valid_response_bytes(&self, response: &[u8]) -> bool {
+        let body = response.strip_suffix(b"\r\n").unwrap_or(response);
+        let last_line = body
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |i| i + 1);
+        self.valid_response(&String::from_utf8_lossy(&response[last_line..]))
+    }
+
     pub fn run_command(&mut self, command: &str) -> Result<String, EmailError> {
-        // The stream must exist first
-        if let Some(ref mut stream) = self.stream {
-            // Create a relevant tag and increment the command index
-            let tag = format!("A{:02}", self.command);
-            self.command += 1;
-            // Format the command appropriately
-            let full_command = format!("{} {}\r\n", tag, command);
-            // let full_command = command;
+        let tag = self.send_command(command)?;
+        self.read_response(&tag)
+    }
+
+    // Tags and sends a command, returning the tag so the response can be read later
+    pub fn send_command(&mut self, command: &str) -> Result<String, EmailError> {
+        let tag = self.next_tag();
+        // Format the command appropriately
+        let full_command = format!("{} {}\r\n", tag, command);
+        if self.debug {
+            print!("Sending Command: {}", full_command);
+        }
+        self.write_bytes(full_command.as_bytes())?;
+        Ok(tag)
+    }
+
+    // Create a relevant tag and increment the command index
+    fn next_tag(&mut self) -> String {
+        let tag = format!("A{:02}", self.command);
+        self.command += 1;
+        tag
+    }
+
+    // Sends a command containing literals. Each text segment is followed by the literal
+    // at the same index, and any segment left over is sent after the last literal.
+    // Synchronizing literals wait for the server's continuation, unless LITERAL+ allows
+    // sending them straight away.
+    pub fn run_literal_command(
+        &mut self,
+        segments: &[String],
+        literals: &[&[u8]],
+    ) -> Result<String, EmailError> {
+        let literal_plus = self.has_capability("LITERAL+")?;
+        let tag = self.next_tag();
+        let mut response = String::new();
+        let mut pending = format!("{} ", tag);
+
+        for (i, literal) in literals.iter().enumerate() {
+            pending.push_str(segments.get(i).map_or("", |segment| segment));
+            if literal_plus {
+                pending.push_str(&format!("{{{}+}}\r\n", literal.len()));
+                self.write_bytes(pending.as_bytes())?;
+            } else {
+                pending.push_str(&format!("{{{}}}\r\n", literal.len()));
+                self.write_bytes(pending.as_bytes())?;
+
+                // Wait for the go ahead, the server may also reject the command outright
+                loop {
+                    let line = String::from_utf8_lossy(&self.read_line()?).to_string();
+                    if line.starts_with('+') {
+                        break;
+                    }
+                    response.push_str(&line);
+                    if line.starts_with(&tag) {
+                        return Ok(response);
+                    }
+                }
+            }
             if self.debug {
-                print!("Sending Command: {}", full_command);
+                print!("Sending Command: {}", pending);
+                println!("<{} byte literal>", literal.len());
             }
-            stream.write_all(full_command.as_bytes())?;
+            self.write_bytes(literal)?;
+            pending = String::new();
+        }
+
+        pending.push_str(segments.get(literals.len()).map_or("", |segment| segment));
+        pending.push_str("\r\n");
+        self.write_bytes(pending.as_bytes())?;
+
+        response.push_str(&self.read_response(&tag)?);
+        Ok(response)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), EmailError> {
+        match self.stream {
+            Some(ref mut stream) => Ok(stream.write_all(bytes)?),
+            None => Err(EmailError::StreamNotConnected),
+        }
+    }
 
-            // Read the response from the server
-            let mut response = String::new();
+    // Reads lines until the tagged completion line arrives
+    pub fn read_response(&mut self, tag: &str) -> Result<String, EmailError> {
+        let response = self.read_response_bytes(tag)?;
+        // Message contents may be in any charset, and bytes that are not UTF-8 are
+        // replaced. Commands that need the exact bytes read them with read_response_bytes.
+        Ok(String::from_utf8(response)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
+
+    // Literals are read by their announced length, so nothing inside a message can be
+    // mistaken for the tagged completion line
+    pub fn read_response_bytes(&mut self, tag: &str) -> Result<Vec<u8>, EmailError> {
+        let completion = format!("{} ", tag);
+        let mut response = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            response.extend_from_slice(&line);
+
+            if let Some(length) = literal_length(&line) {
+                let literal = self.read_bytes(length)?;
+                response.extend_from_slice(&literal);
+                continue;
+            }
+
+            // Check if the line starts with the tag, then break
+            if line.starts_with(completion.as_bytes()) {
+                break;
+            }
+        }
+        Ok(response)
+    }
+
+    pub fn run_command_bytes(&mut self, command: &str) -> Result<Vec<u8>, EmailError> {
+        let tag = self.send_command(command)?;
+        self.read_response_bytes(&tag)
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, EmailError> {
+        match self.stream {
+            Some(ref mut stream) => {
+                let mut bytes = vec![0; length];
+                stream.read_exact(&mut bytes)?;
+                Ok(bytes)
+            }
+            None => Err(EmailError::StreamNotConnected),
+        }
+    }
+
+    // Reads a single \r\n terminated line, including the terminator
+    pub fn read_line(&mut self) -> Result<Vec<u8>, EmailError> {
+        // The stream must exist first
+        if let Some(ref mut stream) = self.stream {
             let mut buffer = [0; 1]; // Read one byte at a time
             let mut prev_byte = None; // To keep track of previous byte
 
@@ -128,39 +322,112 @@ impl<'a> Server<'a> {
             loop {
                 stream.read_exact(&mut buffer)?; // Read one byte
                 let byte = buffer[0];
-
-                // Append byte to response if not null
                 line.push(byte);
 
                 // Check for \r\n termination
                 if prev_byte == Some(b'\r') && byte == b'\n' {
-                    unsafe {
-                        let line_str = String::from_utf8_unchecked(line);
-
-                        // Append the line to the response
-                        response.push_str(&line_str);
-                        // Check if the line starts with the tag, then break
-                        if line_str.starts_with(&tag) {
-                            break;
-                        }
-                    }
-                    line = Vec::<u8>::new();
+                    return Ok(line);
                 }
 
                 // Update prev_byte
                 prev_byte = Some(byte);
             }
-            Ok(response)
         } else {
             Err(EmailError::StreamNotConnected)
         }
     }
 
+    // Waits up to the timeout for a line to start arriving, returning None if none does
+    pub fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, EmailError> {
+        let Some(ref mut stream) = self.stream else {
+            return Err(EmailError::StreamNotConnected);
+        };
+        stream.set_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut buffer = [0; 1];
+        let result = stream.read(&mut buffer);
+        stream.set_timeout(None)?;
+
+        match result {
+            Ok(0) => Err(EmailError::SafeDisconnection),
+            Ok(_) => {
+                let mut line = buffer.to_vec();
+                line.extend_from_slice(&self.read_line()?);
+                Ok(Some(line))
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Asks the server for its capabilities once and caches them
+    pub fn has_capability(&mut self, capability: &str) -> Result<bool, EmailError> {
+        if self.capabilities.is_none() {
+            let response = self.run_command("CAPABILITY")?;
+            let capabilities = response
+                .lines()
+                .filter_map(|line| line.strip_prefix("* CAPABILITY "))
+                .flat_map(|line| line.split_whitespace())
+                .map(|capability| capability.to_uppercase())
+                .collect();
+            self.capabilities = Some(capabilities);
+        }
+        let capability = capability.to_uppercase();
+        Ok(self
+            .capabilities
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.contains(&capability)))
+    }
+
+    // Turns on an extension with ENABLE, returning whether the server accepted it
+    pub fn enable(&mut self, extension: &str) -> Result<bool, EmailError> {
+        if !self.has_capability("ENABLE")? || !self.has_capability(extension)? {
+            return Ok(false);
+        }
+        let response = self.run_command(&format!("ENABLE {}", extension))?;
+        Ok(self.valid_response(&response)
+            && response
+                .lines()
+                .filter_map(|line| line.strip_prefix("* ENABLED"))
+                .any(|line| {
+                    line.split_whitespace()
+                        .any(|enabled| enabled.eq_ignore_ascii_case(extension))
+                }))
+    }
+
+    pub fn create(&mut self, folder: &str) -> Result<String, EmailError> {
+        let command = format!("CREATE {}", folder);
+        let response = self.run_command(&command)?;
+        if self.valid_response(&response) {
+            Ok(response)
+        } else {
+            Err(EmailError::CreateFailure)
+        }
+    }
+
+    // Looks up the UID of a message from its sequence number
+    pub fn fetch_uid(&mut self, message_num: u32) -> Result<u32, EmailError> {
+        let command = format!("FETCH {} (UID)", message_num);
+        let response = self.run_command(&command)?;
+        if !self.valid_response(&response) {
+            return Err(EmailError::MessageNotFound);
+        }
+        response
+            .lines()
+            .filter(|line| line.starts_with("* "))
+            .find_map(|line| fetch_attribute(line, "UID"))
+            .and_then(|uid| uid.parse().ok())
+            .ok_or(EmailError::MessageNotFound)
+    }
+
+    // Ends the session. Errors are ignored, as the connection is being closed anyway.
+    pub fn logout(&mut self) {
+        let _ = self.run_command("LOGOUT");
+    }
+
+    // Closes the connection, which may already have been dropped by the server
     pub fn shutdown(&mut self) {
         if let Some(ref mut stream) = self.stream {
-            stream
-                .shutdown_stream(Shutdown::Both)
-                .expect("shutdown call failed");
+            let _ = stream.shutdown_stream(Shutdown::Both);
         }
     }
 
@@ -185,7 +452,7 @@ impl<'a> Server<'a> {
         if self.valid_response(header) {
             let parsed_response = parse_string(header, 1, 4);
             let unwrapped = self.unwrap_header(&parsed_response);
-            return Ok(unwrapped);
+            Ok(unwrapped)
         } else {
             println!("Message not found");
             std::process::exit(3);
@@ -193,12 +460,19 @@ impl<'a> Server<'a> {
     }
 }
 
+pub fn create_stream(host: &str, use_tls: bool) -> Result<Box<dyn Streamable>, EmailError> {
+    if use_tls {
+        Ok(Box::new(create_ssl_stream(host)?))
+    } else {
+        Ok(Box::new(create_tcp_stream(host)?))
+    }
+}
+
 pub fn create_tcp_stream(host: &str) -> Result<TcpStream, EmailError> {
     let addr = (host, 143)
         .to_socket_addrs()?
-        .filter(|addr| addr.is_ipv4() || addr.is_ipv6())
-        .next()
-        .ok_or_else(|| EmailError::AddressNotFound)?;
+        .find(|addr| addr.is_ipv4() || addr.is_ipv6())
+        .ok_or(EmailError::AddressNotFound)?;
 
     Ok(TcpStream::connect(addr)?)
 }
@@ -207,9 +481,8 @@ pub fn create_ssl_stream(host: &str) -> Result<SslStream<TcpStream>, EmailError>
     let connector = SslConnector::builder(SslMethod::tls()).unwrap().build();
     let addr = (host, 993)
         .to_socket_addrs()?
-        .filter(|addr| addr.is_ipv4() || addr.is_ipv6())
-        .next()
-        .ok_or_else(|| EmailError::AddressNotFound)?;
+        .find(|addr| addr.is_ipv4() || addr.is_ipv6())
+        .ok_or(EmailError::AddressNotFound)?;
 
     let stream = TcpStream::connect(addr)?;
     Ok(connector.connect(host, stream)?)
@@ -0,0 +1,63 @@
+use crate::email_error::EmailError;
+use crate::part::Part;
+use crate::Server;
+
+impl<'a> Server<'a> {
+    pub fn structure(&mut self, message_num: u32) -> Result<String, EmailError> {
+        let message = self.fetch_bytes(message_num)?;
+        Ok(format_structure(&message))
+    }
+}
+
+// Prints the MIME tree a line per part, indented by depth, e.g.
+//
+//   multipart/mixed (5230 bytes)
+//     1 text/plain; charset=utf-8 (quoted-printable, 812 bytes)
+//     2 application/pdf; name="report.pdf" (base64, 4096 bytes, attachment)
+//
+// Sizes are of the body as sent, before any transfer encoding is undone.
+pub fn format_structure(message: &[u8]) -> String {
+    let root = Part::parse(message);
+    let mut output = String::new();
+    for numbered in root.numbered() {
+        let part = numbered.part;
+        output.push_str(&"  ".repeat(numbered.depth));
+        if !numbered.number.is_empty() {
+            output.push_str(&numbered.number);
+            output.push(' ');
+        }
+        output.push_str(&part.content_type.mime_type);
+        for name in ["charset", "name"] {
+            if let Some(value) = part.content_type.parameter(name) {
+                output.push_str(&format!("; {}={}", name, quote(value)));
+            }
+        }
+
+        let mut details = Vec::new();
+        if let Some(encoding) = part.header.get("Content-Transfer-Encoding") {
+            details.push(encoding.trim().to_lowercase());
+        }
+        details.push(format!("{} bytes", part.body.len()));
+        if let Some(disposition) = part.header.get("Content-Disposition") {
+            let disposition = disposition.split(';').next().unwrap_or_default().trim();
+            if !disposition.is_empty() {
+                details.push(disposition.to_lowercase());
+            }
+        }
+        output.push_str(&format!(" ({})\n", details.join(", ")));
+    }
+    output
+}
+
+// Values with spaces or specials are quoted, as in the header
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`{|}~".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
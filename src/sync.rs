@@ -0,0 +1,210 @@
+use crate::cache::FolderCache;
+use crate::email_error::EmailError;
+use crate::helper::to_sequence_set;
+use crate::response::{parse_fetches, parse_vanished};
+use crate::rules::Rule;
+use crate::Server;
+
+// How many new messages are downloaded per FETCH
+const BATCH_MESSAGES: usize = 50;
+
+// What a sync changed in the local cache
+pub struct SyncReport {
+    pub new: Vec<u32>,
+    pub expunged: Vec<u32>,
+    pub flag_changes: usize,
+}
+
+impl<'a> Server<'a> {
+    pub fn sync(
+        &mut self,
+        folder: &str,
+        cache_root: &str,
+        account: &str,
+        rules: &[Rule],
+        dry_run: bool,
+    ) -> Result<String, EmailError> {
+        let mut cache = FolderCache::open(cache_root, account, folder)?;
+        let report = self.sync_folder(folder, &mut cache)?;
+
+        let mut output = format!(
+            "{}: {} new, {} expunged, {} flag changes\n",
+            folder.trim_matches('"'),
+            report.new.len(),
+            report.expunged.len(),
+            report.flag_changes
+        );
+        // Only new mail is filtered, the rules have already seen everything else
+        if !rules.is_empty() && !report.new.is_empty() {
+            output.push_str(&self.apply_rules(rules, &report.new, dry_run)?);
+        }
+        Ok(output)
+    }
+
+    // Brings the cache up to date with the folder, downloading only messages it lacks.
+    // With QRESYNC the SELECT itself reports expunges and flag changes since the last
+    // sync, with CONDSTORE only changed flags are fetched, otherwise everything is compared.
+    pub fn sync_folder(
+        &mut self,
+        folder: &str,
+        cache: &mut FolderCache,
+    ) -> Result<SyncReport, EmailError> {
+        let qresync = self.enable("QRESYNC")?;
+        let condstore = qresync || self.has_capability("CONDSTORE")?;
+        let resync = qresync && cache.uid_validity != 0 && cache.highest_modseq != 0;
+
+        let response = if resync {
+            // Listing the cached UIDs limits VANISHED to messages we actually hold
+            let known: Vec<u32> = cache.messages.keys().copied().collect();
+            let mut parameters = format!("{} {}", cache.uid_validity, cache.highest_modseq);
+            if !known.is_empty() {
+                parameters.push(' ');
+                parameters.push_str(&to_sequence_set(&known));
+            }
+            self.select_with(folder, &format!("(QRESYNC ({}))", parameters))?
+        } else if condstore {
+            self.select_with(folder, "(CONDSTORE)")?
+        } else {
+            self.select(folder)?
+        };
+
+        // A new UIDVALIDITY means the old UIDs no longer refer to the same messages
+        if cache.uid_validity != self.mailbox.uid_validity {
+            cache.clear(self.mailbox.uid_validity)?;
+        }
+
+        let (expunged, flag_changes, new) = if resync && cache.highest_modseq != 0 {
+            self.resync_changes(&response, cache)?
+        } else {
+            self.compare_changes(condstore, cache)?
+        };
+
+        for batch in new.chunks(BATCH_MESSAGES) {
+            let items = "UID FLAGS INTERNALDATE BODY.PEEK[]";
+            for fetch in self.uid_fetch(&to_sequence_set(batch), items)? {
+                // Unsolicited FETCH responses carry flags only and are not new messages
+                let Some(message) = fetch.get("BODY[]") else {
+                    continue;
+                };
+                if !batch.contains(&fetch.uid()) {
+                    continue;
+                }
+                cache.store(
+                    fetch.uid(),
+                    &fetch.text("INTERNALDATE"),
+                    fetch.flags(),
+                    message,
+                )?;
+            }
+            // Save as we go so an interrupted sync keeps what it downloaded
+            cache.save()?;
+        }
+
+        cache.uid_next = self
+            .mailbox
+            .uid_next
+            .max(new.last().map_or(0, |uid| uid + 1))
+            .max(cache.uid_next);
+        cache.highest_modseq = self.mailbox.highest_modseq;
+        cache.save()?;
+
+        Ok(SyncReport {
+            new,
+            expunged,
+            flag_changes,
+        })
+    }
+
+    // Reads the VANISHED and FETCH responses that came back from SELECT (QRESYNC ...)
+    fn resync_changes(
+        &mut self,
+        response: &str,
+        cache: &mut FolderCache,
+    ) -> Result<(Vec<u32>, usize, Vec<u32>), EmailError> {
+        let vanished = parse_vanished(response);
+        let expunged: Vec<u32> = cache
+            .messages
+            .keys()
+            .copied()
+            .filter(|&uid| vanished.contains(uid))
+            .collect();
+        for &uid in &expunged {
+            cache.remove(uid)?;
+        }
+
+        let flag_changes = parse_fetches(response.as_bytes())
+            .iter()
+            .filter(|fetch| cache.set_flags(fetch.uid(), fetch.flags()))
+            .count();
+
+        // Only UIDs from the last UIDNEXT on can be new. A range ending in * always
+        // matches the last message, so anything below is filtered out again
+        let first = cache.uid_next.max(1);
+        let new = self
+            .uid_search(&format!("UID {}:*", first))?
+            .into_iter()
+            .filter(|uid| *uid >= first && !cache.messages.contains_key(uid))
+            .collect();
+
+        Ok((expunged, flag_changes, new))
+    }
+
+    // Lists every UID on the server to find expunged and new messages
+    fn compare_changes(
+        &mut self,
+        condstore: bool,
+        cache: &mut FolderCache,
+    ) -> Result<(Vec<u32>, usize, Vec<u32>), EmailError> {
+        let mut server_uids = self.uid_search("ALL")?;
+        server_uids.sort_unstable();
+
+        // Anything cached that the server no longer lists has been expunged
+        let expunged: Vec<u32> = cache
+            .messages
+            .keys()
+            .copied()
+            .filter(|uid| server_uids.binary_search(uid).is_err())
+            .collect();
+        for &uid in &expunged {
+            cache.remove(uid)?;
+        }
+
+        let changed_since = if condstore { cache.highest_modseq } else { 0 };
+        let flag_changes = self.refresh_flags(cache, changed_since)?;
+
+        // Everything below the last UIDNEXT is already cached, so this is the new mail
+        // plus anything an interrupted sync did not get to
+        let new = server_uids
+            .into_iter()
+            .filter(|uid| !cache.messages.contains_key(uid))
+            .collect();
+
+        Ok((expunged, flag_changes, new))
+    }
+
+    // Re-reads the flags of cached messages, only those changed since a MODSEQ if given
+    fn refresh_flags(
+        &mut self,
+        cache: &mut FolderCache,
+        changed_since: u64,
+    ) -> Result<usize, EmailError> {
+        let Some(&last) = cache.messages.keys().next_back() else {
+            return Ok(0);
+        };
+
+        let uid_set = format!("1:{}", last);
+        let fetches = if changed_since > 0 {
+            self.uid_fetch_changed_since(&uid_set, "UID FLAGS", changed_since)?
+        } else {
+            self.uid_fetch(&uid_set, "UID FLAGS")?
+        };
+
+        let mut changes = 0;
+        for fetch in fetches {
+            if cache.set_flags(fetch.uid(), fetch.flags()) {
+                changes += 1;
+            }
+        }
+        Ok(changes)
+    }
+}
@@ -0,0 +1,177 @@
+use crate::email_error::EmailError;
+use crate::list::list_entry;
+use crate::rules::Rule;
+use crate::Server;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Servers may drop an IDLE after 30 minutes (RFC 2177), so it is renewed well before
+const IDLE_RENEWAL: Duration = Duration::from_secs(28 * 60);
+
+pub const DEFAULT_INTERVAL: u64 = 60;
+
+impl<'a> Server<'a> {
+    // Reports changes to the selected folder as they happen, using IDLE where the server
+    // supports it and polling with NOOP every interval seconds otherwise. New messages are
+    // piped to the command, if one is given, and then run through the rules.
+    pub fn watch(
+        &mut self,
+        interval: u64,
+        command: &str,
+        rules: &[Rule],
+        dry_run: bool,
+    ) -> Result<String, EmailError> {
+        let idle = self.has_capability("IDLE")?;
+        let mut last_uid = match self.mailbox.uid_next {
+            0 => self.uid_search("ALL")?.into_iter().max().unwrap_or(0),
+            uid_next => uid_next - 1,
+        };
+
+        loop {
+            let events = if idle {
+                self.idle()?
+            } else {
+                thread::sleep(Duration::from_secs(interval));
+                let response = self.run_command("NOOP")?;
+                untagged_lines(&response)
+            };
+
+            let mut arrived = false;
+            for event in &events {
+                let mut words = event.trim_start_matches("* ").split_whitespace();
+                let number = words.next().unwrap_or_default();
+                // With QRESYNC enabled, expunges are reported by UID as VANISHED
+                if number.eq_ignore_ascii_case("VANISHED") {
+                    let uids = words.filter(|word| !word.eq_ignore_ascii_case("(EARLIER)"));
+                    report(&format!(
+                        "Expunged messages with UIDs {}\n",
+                        uids.collect::<Vec<&str>>().join(" ")
+                    ));
+                    continue;
+                }
+                match words.next().map(|word| word.to_uppercase()).as_deref() {
+                    Some("EXISTS") => arrived = true,
+                    Some("EXPUNGE") => report(&format!("Expunged message {}\n", number)),
+                    Some("FETCH") => {
+                        if let Some(flags) = event_flags(event) {
+                            report(&format!("Flags changed on message {}: {}\n", number, flags));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if arrived {
+                let new = self.report_new(last_uid, command)?;
+                last_uid = new.iter().copied().max().unwrap_or(last_uid);
+                if !rules.is_empty() && !new.is_empty() {
+                    report(&self.apply_rules(rules, &new, dry_run)?);
+                }
+            }
+        }
+    }
+
+    // Idles until the folder changes or the renewal time is reached, returning any
+    // untagged responses received
+    fn idle(&mut self) -> Result<Vec<String>, EmailError> {
+        let tag = self.send_command("IDLE")?;
+        let mut events = Vec::new();
+        loop {
+            let line = String::from_utf8_lossy(&self.read_line()?).to_string();
+            if line.starts_with('+') {
+                break;
+            }
+            if line.starts_with(&format!("{} ", tag)) {
+                return Err(EmailError::IdleFailure);
+            }
+            events.push(line);
+        }
+
+        let started = Instant::now();
+        while events.is_empty() {
+            let remaining = IDLE_RENEWAL.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            if let Some(line) = self.read_line_timeout(remaining)? {
+                events.push(String::from_utf8_lossy(&line).to_string());
+            }
+        }
+
+        self.write_bytes(b"DONE\r\n")?;
+        let response = self.read_response(&tag)?;
+        events.extend(untagged_lines(&response));
+        Ok(events)
+    }
+
+    // Fetches the messages above the last UID seen, returning their UIDs
+    fn report_new(&mut self, last_uid: u32, command: &str) -> Result<Vec<u32>, EmailError> {
+        let items = if command.is_empty() {
+            "UID BODY.PEEK[HEADER.FIELDS (SUBJECT)]"
+        } else {
+            "UID BODY.PEEK[HEADER.FIELDS (SUBJECT)] BODY.PEEK[]"
+        };
+        let fetches = self.uid_fetch(&format!("{}:*", last_uid + 1), items)?;
+
+        let mut new = Vec::new();
+        // n:* always includes the last message, even when its UID is below n
+        for fetch in fetches.iter().filter(|fetch| fetch.uid() > last_uid) {
+            let subject_line = fetch
+                .text("BODY[HEADER.FIELDS (SUBJECT)]")
+                .split("\r\n")
+                .collect::<String>();
+            report(&format!(
+                "New message {}",
+                list_entry(&fetch.uid().to_string(), &subject_line, false)
+            ));
+
+            // A failing command is reported but does not stop the watch
+            if !command.is_empty() && !hand_off(command, fetch.get("BODY[]").unwrap_or_default()) {
+                eprintln!("Command failed for message {}", fetch.uid());
+            }
+            new.push(fetch.uid());
+        }
+        Ok(new)
+    }
+}
+
+fn untagged_lines(response: &str) -> Vec<String> {
+    response
+        .lines()
+        .filter(|line| line.starts_with("* "))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// Extracts the flag list from an unsolicited FETCH, e.g. * 3 FETCH (FLAGS (\Seen)).
+// Only ASCII is upper-cased, so offsets into the copy are offsets into the event.
+fn event_flags(event: &str) -> Option<&str> {
+    let start = event.to_ascii_uppercase().find("FLAGS (")? + "FLAGS (".len();
+    let length = event[start..].find(')')?;
+    Some(&event[start..start + length])
+}
+
+// Events are printed as they happen rather than when the command finishes
+fn report(event: &str) {
+    print!("{}", event);
+    let _ = std::io::stdout().flush();
+}
+
+// Runs the command through the shell with the message on its standard input
+fn hand_off(command: &str, message: &[u8]) -> bool {
+    let Ok(mut child) = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        return false;
+    };
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(message).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}